@@ -0,0 +1,290 @@
+use log::{info, warn};
+use rand::{prelude::StdRng, Rng, SeedableRng};
+
+use common::{
+    account::MatchResult,
+    battle::message::ServerMessage,
+    deserialize,
+    lobby::{LobbyMessage, LobbyRequest},
+    pokedex::pokemon::{owned::SavedPokemon, party::Party},
+    serialize, BattleSetup, BundleHash, ConnectMessage, Id, NetClientMessage, NetServerMessage,
+    Player, RematchMessage, RematchVote, PROTOCOL_VERSION,
+};
+
+use crate::{
+    net::{Connection, Endpoint, ReliableSocket},
+    strategy::{Strategy, StrategyKind},
+};
+
+/// Seconds between pings sent to the server.
+const PING_INTERVAL: f32 = 1.0;
+
+/// What a bot joins as and how long it plays for.
+#[derive(Clone)]
+pub struct BotSettings {
+    pub name: String,
+    /// Password of the bot's account, if the server has accounts.
+    pub password: Option<String>,
+    /// Party to submit instead of using the one the server generates.
+    pub team: Option<Party<SavedPokemon>>,
+    /// Wait for ranked battles instead of quick matches.
+    pub ranked: bool,
+    /// Number of battles to play before leaving, or no limit.
+    pub battles: Option<u32>,
+    pub strategy: StrategyKind,
+}
+
+/// Results of the battles a bot has played.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BotStats {
+    pub battles: u32,
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BotState {
+    /// Waiting for the server to accept the bot
+    Joining,
+    /// Waiting for a battle to start
+    Waiting,
+    Battling,
+    /// Played all of its battles and left
+    Done,
+    /// The server would not let the bot play, for the given reason
+    Failed(String),
+}
+
+/// A client without a window that joins a server and battles with a strategy.
+pub struct Bot {
+    connection: Box<dyn Connection>,
+    settings: BotSettings,
+    state: BotState,
+    strategy: Box<dyn Strategy>,
+    setup: Option<BattleSetup>,
+    /// The server runs a tournament, which starts the bot's battles itself.
+    tournament: bool,
+    /// Joined but not queued for a match yet, until the lobby shows if there is a tournament.
+    unqueued: bool,
+    stats: BotStats,
+    /// Seconds since the last ping was sent.
+    ping: f32,
+    random: StdRng,
+}
+
+impl Bot {
+    /// Connect to a server and ask to join it.
+    pub fn connect(address: Endpoint, settings: BotSettings, seed: u64) -> Self {
+        let mut random = StdRng::seed_from_u64(seed);
+        let socket = ReliableSocket::connect(address, random.gen());
+        Self::new(Box::new(socket), settings, random.gen())
+    }
+
+    /// Ask to join the server at the other end of a connection.
+    pub fn new(connection: Box<dyn Connection>, settings: BotSettings, seed: u64) -> Self {
+        let mut random = StdRng::seed_from_u64(seed);
+        let mut bot = Self {
+            connection,
+            strategy: settings.strategy.build(random.gen()),
+            settings,
+            state: BotState::Joining,
+            setup: None,
+            tournament: false,
+            unqueued: false,
+            stats: Default::default(),
+            ping: 0.0,
+            random,
+        };
+        bot.request_join();
+        bot
+    }
+
+    pub fn name(&self) -> &str {
+        &self.settings.name
+    }
+
+    pub fn state(&self) -> &BotState {
+        &self.state
+    }
+
+    pub fn stats(&self) -> BotStats {
+        self.stats
+    }
+
+    /// Whether the bot has stopped playing, either done or failed.
+    pub fn finished(&self) -> bool {
+        matches!(self.state, BotState::Done | BotState::Failed(..))
+    }
+
+    /// Handle everything the server has sent, ping it and resend anything it has not acknowledged.
+    pub fn update(&mut self, delta: f32) {
+        if self.finished() {
+            return;
+        }
+        self.ping += delta;
+        if self.ping >= PING_INTERVAL {
+            self.ping -= PING_INTERVAL;
+            self.send(&NetClientMessage::Ping);
+        }
+        while let Some(bytes) = self.connection.receive() {
+            match deserialize::<NetServerMessage<Id>>(&bytes) {
+                Ok(message) => self.receive(message),
+                Err(err) => warn!(
+                    "{} could not deserialize message with error {}",
+                    self.settings.name, err
+                ),
+            }
+        }
+        self.connection.update(delta);
+    }
+
+    /// Tell the server the bot is leaving.
+    pub fn leave(&mut self) {
+        self.send(&NetClientMessage::Leave);
+        self.connection.update(0.0);
+        if !self.finished() {
+            self.state = BotState::Done;
+        }
+    }
+
+    fn receive(&mut self, message: NetServerMessage<Id>) {
+        match message {
+            NetServerMessage::Validate(message) => match message {
+                ConnectMessage::CanJoin(party) => match self.settings.team.clone() {
+                    Some(team) => self.send(&NetClientMessage::Team(team)),
+                    None => match party {
+                        Some(..) => self.join(),
+                        None => self.fail("the server requires a team but none was given"),
+                    },
+                },
+                ConnectMessage::TeamAccepted => self.join(),
+                ConnectMessage::Session(..) => {
+                    self.state = BotState::Waiting;
+                    // A tournament is announced before the rooms are listed
+                    self.unqueued = true;
+                    self.send(&NetClientMessage::Lobby(LobbyRequest::Rooms));
+                }
+                other => self.fail(&format!("the server sent {:?}", other)),
+            },
+            NetServerMessage::Setup(setup) => {
+                self.strategy = self.settings.strategy.build(self.random.gen());
+                self.strategy.begin(setup.format.active());
+                self.setup = Some(setup);
+                self.state = BotState::Battling;
+            }
+            NetServerMessage::Game(message) => {
+                let winner = match &message {
+                    ServerMessage::GameEnd(winner) => Some(*winner),
+                    _ => None,
+                };
+                for action in self.strategy.respond(message) {
+                    self.send(&NetClientMessage::Game(action));
+                }
+                if let Some(winner) = winner {
+                    self.end(winner);
+                }
+            }
+            NetServerMessage::Rematch(RematchMessage::Offer(..)) => {
+                // Bots join again instead, to be matched with whoever is waiting
+                self.send(&NetClientMessage::Rematch(RematchVote {
+                    accept: false,
+                    same_team: true,
+                }));
+            }
+            NetServerMessage::Lobby(LobbyMessage::Error(err)) => {
+                warn!("{} was refused by the lobby: {}", self.settings.name, err)
+            }
+            NetServerMessage::Lobby(LobbyMessage::Rooms(..)) if self.unqueued => {
+                self.unqueued = false;
+                if !self.tournament {
+                    self.queue();
+                }
+            }
+            NetServerMessage::Tournament(..) => self.tournament = true,
+            NetServerMessage::Lobby(..)
+            | NetServerMessage::Pong
+            | NetServerMessage::Clock(..)
+            | NetServerMessage::TimedOut
+            | NetServerMessage::Chat(..)
+            | NetServerMessage::ChatRejected(..)
+            | NetServerMessage::Rematch(..)
+            | NetServerMessage::Stats(..)
+            | NetServerMessage::Ranking(..) => (),
+        }
+    }
+
+    /// Record the result of a battle, then leave or join again for the next one.
+    fn end(&mut self, winner: Option<Id>) {
+        if self.state != BotState::Battling {
+            return;
+        }
+        let you = self.setup.as_ref().and_then(|setup| setup.you);
+        let result = match winner {
+            None => MatchResult::Draw,
+            Some(winner) if you == Some(winner) => MatchResult::Win,
+            Some(..) => MatchResult::Loss,
+        };
+        self.stats.battles += 1;
+        match result {
+            MatchResult::Win => self.stats.wins += 1,
+            MatchResult::Loss => self.stats.losses += 1,
+            MatchResult::Draw => self.stats.draws += 1,
+        }
+        info!(
+            "{} finished battle {} with a {:?}",
+            self.settings.name, self.stats.battles, result
+        );
+        self.setup = None;
+        match self.settings.battles {
+            Some(battles) if self.stats.battles >= battles => self.leave(),
+            // Tournaments put players back in line for their next match
+            _ if self.tournament => self.state = BotState::Waiting,
+            // Ranked players are put back in the lobby to queue again
+            _ if self.settings.ranked => {
+                self.state = BotState::Waiting;
+                self.queue();
+            }
+            _ => self.request_join(),
+        }
+    }
+
+    fn request_join(&mut self) {
+        self.state = BotState::Joining;
+        self.send(&NetClientMessage::RequestJoin(
+            PROTOCOL_VERSION,
+            BundleHash::BUILT,
+        ));
+    }
+
+    fn join(&mut self) {
+        self.send(&NetClientMessage::Join(Player {
+            name: self.settings.name.clone(),
+            password: self.settings.password.clone(),
+        }));
+    }
+
+    /// Wait for a quick or ranked match.
+    fn queue(&mut self) {
+        let request = match self.settings.ranked {
+            true => LobbyRequest::RankedMatch,
+            false => LobbyRequest::QuickMatch,
+        };
+        self.send(&NetClientMessage::Lobby(request));
+    }
+
+    fn fail(&mut self, reason: &str) {
+        warn!("{} stopped as {}", self.settings.name, reason);
+        self.state = BotState::Failed(reason.to_owned());
+    }
+
+    fn send(&mut self, message: &NetClientMessage<Id>) {
+        match serialize(message) {
+            Ok(bytes) => self.connection.send(bytes),
+            Err(err) => warn!(
+                "{} could not serialize message with error {}",
+                self.settings.name, err
+            ),
+        }
+    }
+}
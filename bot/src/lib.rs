@@ -0,0 +1,9 @@
+//! Headless clients that join a battle server and play on their own, for load testing.
+
+extern crate firecore_battle_net as common;
+
+mod bot;
+pub mod net;
+pub mod strategy;
+
+pub use bot::*;
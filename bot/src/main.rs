@@ -0,0 +1,183 @@
+use std::{
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use log::{error, info, LevelFilter};
+use simple_logger::SimpleLogger;
+
+use firecore_battle_net::{
+    pokedex::pokemon::{owned::SavedPokemon, party::Party},
+    DEFAULT_PORT,
+};
+use pokemon_battle_bot::{Bot, BotSettings, BotState, BotStats, StrategyKind};
+
+/// Bots that join a battle server and play against whoever they are matched with.
+#[derive(Parser)]
+#[clap(version)]
+struct Args {
+    /// Address of the server, with an optional port
+    address: String,
+    /// Number of bots to run
+    #[clap(short = 'n', long, default_value = "2")]
+    count: usize,
+    /// How the bots pick their moves: random or ai
+    #[clap(short, long, default_value = "random")]
+    strategy: StrategyKind,
+    /// Number of battles each bot plays before leaving, or no limit
+    #[clap(short, long)]
+    battles: Option<u32>,
+    /// Name of the bots, numbered from 1
+    #[clap(long, default_value = "bot")]
+    name: String,
+    /// Password the bots log in with
+    #[clap(long)]
+    password: Option<String>,
+    /// Path to a RON file with the party the bots submit
+    #[clap(short, long, parse(from_os_str))]
+    team: Option<PathBuf>,
+    /// Wait for ranked battles instead of quick matches
+    #[clap(long)]
+    ranked: bool,
+    /// Seed for the bots' random number generators
+    #[clap(long)]
+    seed: Option<u64>,
+    /// Seconds to run for before the bots leave
+    #[clap(short, long)]
+    duration: Option<f32>,
+    /// One of off, error, warn, info, debug or trace
+    #[clap(short, long)]
+    log_level: Option<LevelFilter>,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    SimpleLogger::new()
+        .with_level(args.log_level.unwrap_or(LevelFilter::Info))
+        .init()
+        .unwrap_or_else(|err| panic!("Could not initialize logger with error {}", err));
+
+    let address = find_address(&args.address).unwrap_or_else(|err| {
+        error!(
+            "Could not find server at {} with error {}",
+            args.address, err
+        );
+        std::process::exit(1);
+    });
+
+    let team = args.team.as_ref().map(|path| load_team(path));
+
+    let seed = args.seed.unwrap_or_else(rand::random);
+
+    let mut bots = (1..=args.count)
+        .map(|number| {
+            let settings = BotSettings {
+                name: format!("{}{}", args.name, number),
+                password: args.password.clone(),
+                team: team.clone(),
+                ranked: args.ranked,
+                battles: args.battles,
+                strategy: args.strategy,
+            };
+            Bot::connect(address, settings, seed.wrapping_add(number as u64))
+        })
+        .collect::<Vec<_>>();
+
+    info!("Connecting {} bot(s) to {}", bots.len(), address);
+
+    let running = Arc::new(AtomicBool::new(true));
+
+    // Leave on control-c
+
+    let running_handle = running.clone();
+
+    ctrlc::set_handler(move || running_handle.store(false, Ordering::Relaxed))
+        .unwrap_or_else(|err| panic!("Could not set Ctrl + C handler with error {}", err));
+
+    let start = Instant::now();
+    let mut last = start;
+
+    while running.load(Ordering::Relaxed) && !bots.iter().all(Bot::finished) {
+        if let Some(duration) = args.duration {
+            if start.elapsed().as_secs_f32() >= duration {
+                break;
+            }
+        }
+        let now = Instant::now();
+        let delta = now.duration_since(last).as_secs_f32();
+        last = now;
+        for bot in bots.iter_mut() {
+            bot.update(delta);
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    for bot in bots.iter_mut().filter(|bot| !bot.finished()) {
+        bot.leave();
+    }
+
+    // Summarize
+
+    let mut total = BotStats::default();
+    let mut failed = 0;
+    for bot in bots.iter() {
+        let stats = bot.stats();
+        total.battles += stats.battles;
+        total.wins += stats.wins;
+        total.losses += stats.losses;
+        total.draws += stats.draws;
+        if let BotState::Failed(reason) = bot.state() {
+            error!("{} failed: {}", bot.name(), reason);
+            failed += 1;
+        }
+    }
+
+    info!(
+        "{} bot(s) played {} battle(s) in {:.1} seconds: {} won, {} lost, {} drawn",
+        bots.len(),
+        total.battles,
+        start.elapsed().as_secs_f32(),
+        total.wins,
+        total.losses,
+        total.draws,
+    );
+
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// Load a party from a RON file for the bots to submit.
+fn load_team(path: &PathBuf) -> Party<SavedPokemon> {
+    let data = std::fs::read_to_string(path)
+        .unwrap_or_else(|err| panic!("Could not read team file at {:?} with error {}", path, err));
+    ron::from_str(&data)
+        .unwrap_or_else(|err| panic!("Could not parse team file at {:?} with error {}", path, err))
+}
+
+fn find_address(address: &str) -> Result<SocketAddr, std::io::Error> {
+    if let Ok(address) = address.parse::<SocketAddr>() {
+        return Ok(address);
+    }
+    if let Ok(ip) = address.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, DEFAULT_PORT));
+    }
+    let resolved = match address.contains(':') {
+        true => address.to_socket_addrs()?.next(),
+        false => (address, DEFAULT_PORT).to_socket_addrs()?.next(),
+    };
+    resolved.ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "The address did not resolve to anything.",
+        )
+    })
+}
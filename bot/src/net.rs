@@ -0,0 +1,26 @@
+pub use common::socket::{Endpoint, ReliableSocket};
+
+/// Carries a bot's payloads to and from the server, reliably and in the order they were sent.
+pub trait Connection {
+    fn send(&mut self, bytes: Vec<u8>);
+
+    /// Receive the next payload from the server, in the order it was sent.
+    fn receive(&mut self) -> Option<Vec<u8>>;
+
+    /// Send frames that have not been acknowledged in time again.
+    fn update(&mut self, delta: f32);
+}
+
+impl Connection for ReliableSocket {
+    fn send(&mut self, bytes: Vec<u8>) {
+        ReliableSocket::send(self, bytes)
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        ReliableSocket::receive(self)
+    }
+
+    fn update(&mut self, delta: f32) {
+        ReliableSocket::update(self, delta)
+    }
+}
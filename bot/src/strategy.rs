@@ -0,0 +1,145 @@
+use core::str::FromStr;
+
+use rand::{prelude::StdRng, Rng, SeedableRng};
+
+use common::{
+    battle::{
+        ai::BattlePlayerAi,
+        endpoint::BattleEndpoint,
+        message::{ClientMessage, ServerMessage},
+        moves::BattleMove,
+    },
+    Id,
+};
+
+/// How a bot decides what to do in a battle.
+pub trait Strategy {
+    /// Get ready for a battle with the given number of active pokemon per player.
+    fn begin(&mut self, _active: usize) {}
+
+    /// React to a game message, returning the actions to send back.
+    fn respond(&mut self, message: ServerMessage<Id>) -> Vec<ClientMessage<Id>>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StrategyKind {
+    Random,
+    Ai,
+}
+
+impl StrategyKind {
+    pub fn build(&self, seed: u64) -> Box<dyn Strategy> {
+        match self {
+            Self::Random => Box::new(Random::new(seed)),
+            Self::Ai => Box::new(Ai::new(seed)),
+        }
+    }
+}
+
+impl FromStr for StrategyKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "random" => Ok(Self::Random),
+            "ai" => Ok(Self::Ai),
+            other => Err(format!("Unknown strategy {}, use random or ai", other)),
+        }
+    }
+}
+
+/// Plays like the AI opponents the server fills empty slots with.
+pub struct Ai(Box<dyn BattleEndpoint<Id>>);
+
+impl Ai {
+    pub fn new(seed: u64) -> Self {
+        Self(Box::new(BattlePlayerAi::new(StdRng::seed_from_u64(seed))))
+    }
+}
+
+impl Strategy for Ai {
+    fn respond(&mut self, message: ServerMessage<Id>) -> Vec<ClientMessage<Id>> {
+        self.0.send(message);
+        let endpoint = &mut self.0;
+        std::iter::from_fn(|| endpoint.receive().ok()).collect()
+    }
+}
+
+/// Picks a random move for each active pokemon.
+/// Faint replacements and everything else the battle asks for are left to the AI.
+pub struct Random {
+    random: StdRng,
+    ai: Ai,
+    /// Number of active pokemon per player.
+    slots: usize,
+    /// Number of moves each party member knows, as the battle told it when it began.
+    moves: Vec<usize>,
+    /// Party member in each active slot.
+    active: Vec<Option<usize>>,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        let mut random = StdRng::seed_from_u64(seed);
+        Self {
+            ai: Ai::new(random.gen()),
+            random,
+            slots: 1,
+            moves: Vec::new(),
+            active: Vec::new(),
+        }
+    }
+}
+
+impl Strategy for Random {
+    fn begin(&mut self, active: usize) {
+        self.slots = active;
+    }
+
+    fn respond(&mut self, message: ServerMessage<Id>) -> Vec<ClientMessage<Id>> {
+        if let ServerMessage::Begin(data) = &message {
+            // Generated parties only learn their moves once the battle begins
+            let party = &data.local.pokemon;
+            self.moves = party.iter().map(|pokemon| pokemon.moves.len()).collect();
+            // The first party members start out
+            self.active = (0..self.slots)
+                .map(|index| match index < party.len() {
+                    true => Some(index),
+                    false => None,
+                })
+                .collect();
+        }
+        let selecting = matches!(message, ServerMessage::StartSelecting);
+        let actions = self.ai.respond(message);
+        let mut responses = Vec::with_capacity(actions.len());
+        for action in actions {
+            match action {
+                ClientMessage::Move(slot, BattleMove::Move(chosen, target)) if selecting => {
+                    let known = self
+                        .active
+                        .get(slot)
+                        .copied()
+                        .flatten()
+                        .and_then(|member| self.moves.get(member))
+                        .copied()
+                        .unwrap_or_default();
+                    let action = match known {
+                        // Keep the AI's choice when the pokemon's moves are not known
+                        0 => BattleMove::Move(chosen, target),
+                        known => BattleMove::Move(self.random.gen_range(0..known), None),
+                    };
+                    responses.push(ClientMessage::Move(slot, action));
+                }
+                ClientMessage::Move(slot, BattleMove::Switch(member))
+                | ClientMessage::ReplaceFaint(slot, member) => {
+                    if let Some(active) = self.active.get_mut(slot) {
+                        *active = Some(member);
+                    }
+                    responses.push(action);
+                }
+                action => responses.push(action),
+            }
+        }
+        responses
+    }
+}
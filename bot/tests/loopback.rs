@@ -0,0 +1,104 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    rc::Rc,
+};
+
+use firecore_battle_net::tournament::TournamentFormat;
+use pokemon_battle_bot::{net::Connection, Bot, BotSettings, BotState, StrategyKind};
+use pokemon_battle_server::{
+    configuration::{Configuration, TournamentSettings},
+    loopback::{Loopback, LoopbackClient},
+    Server,
+};
+
+/// Most updates two bots get to finish their battles before the test fails.
+const MAX_UPDATES: usize = 10_000;
+
+/// Seconds that pass between updates.
+const DELTA: f32 = 0.1;
+
+/// A bot's end of the in-memory transport.
+struct Client(LoopbackClient);
+
+impl Connection for Client {
+    fn send(&mut self, bytes: Vec<u8>) {
+        self.0.send(bytes)
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        self.0.receive()
+    }
+
+    // Loopback packets cannot be lost, so there is nothing to resend
+    fn update(&mut self, _delta: f32) {}
+}
+
+fn bot(loopback: &Loopback, port: u16, name: &str, strategy: StrategyKind) -> Bot {
+    let client = loopback.client(SocketAddr::from((Ipv4Addr::LOCALHOST, port)));
+    let settings = BotSettings {
+        name: name.to_owned(),
+        password: None,
+        team: None,
+        ranked: false,
+        battles: Some(1),
+        strategy,
+    };
+    Bot::new(Box::new(Client(client)), settings, port as u64)
+}
+
+/// Have Red and Blue play one battle each on a server, until both are done.
+fn play(configuration: Configuration) -> Vec<Bot> {
+    let loopback = Loopback::new();
+    let mut server = Server::new(configuration, Rc::new(loopback.clone())).unwrap();
+
+    let mut bots = vec![
+        bot(&loopback, 1, "Red", StrategyKind::Random),
+        bot(&loopback, 2, "Blue", StrategyKind::Ai),
+    ];
+
+    for _ in 0..MAX_UPDATES {
+        if bots.iter().all(Bot::finished) {
+            break;
+        }
+        server.update(DELTA);
+        for bot in bots.iter_mut() {
+            bot.update(DELTA);
+        }
+    }
+
+    for bot in bots.iter() {
+        assert_eq!(bot.state(), &BotState::Done, "{}", bot.name());
+        assert_eq!(bot.stats().battles, 1, "{}", bot.name());
+    }
+
+    bots
+}
+
+#[test]
+fn bots_play_a_quick_match() {
+    let bots = play(Configuration {
+        seed: Some(0),
+        ..Default::default()
+    });
+
+    // Each battle is won by one bot and lost by the other, or drawn by both
+    let (red, blue) = (bots[0].stats(), bots[1].stats());
+    assert_eq!(red.wins, blue.losses);
+    assert_eq!(red.losses, blue.wins);
+    assert_eq!(red.draws, blue.draws);
+}
+
+#[test]
+fn bots_wait_for_their_tournament_match() {
+    // Both bots are in the tournament's only match, which the server starts for them
+    play(Configuration {
+        seed: Some(0),
+        tournament: Some(TournamentSettings {
+            format: TournamentFormat::SingleElimination,
+            players: vec!["Red".to_owned(), "Blue".to_owned()],
+            rounds: None,
+            start_after: 300,
+        }),
+        ..Default::default()
+    });
+}
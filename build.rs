@@ -1,8 +1,14 @@
+#[path = "src/hash.rs"]
+mod hash;
+
 fn main() {
     #[cfg(feature = "build")] {
 
         use std::path::Path;
         use firecore_pokedex_builder::compile as build_pokedex;
+        use firecore_battle_builder::compile as build_battle;
+
+        println!("cargo:rerun-if-changed=assets");
 
         let input = Path::new("assets/pokedex");
 
@@ -24,10 +30,61 @@ fn main() {
             )
         });
 
+        let battle = input.join("battle");
+
+        let battle = build_battle(&battle, &battle.join("scripts"));
+
+        let data = bincode::serialize(&battle)
+        .unwrap_or_else(|err| panic!("Could not serialize battle move binary with error {}", err));
+
+        let output = "battle.bin";
+
+        std::fs::write(output, &data).unwrap_or_else(|err| {
+            panic!(
+                "Cannot create / write to battle binary file at {:?} with error {}",
+                output, err
+            )
+        });
+
         // #[cfg(windows)]
         // winres::WindowsResource::new()
         //     .set_icon("icon.ico")
         //     .compile()
         //     .unwrap();   
     }
-}
\ No newline at end of file
+
+    #[cfg(not(feature = "build"))] {
+        println!("cargo:rerun-if-changed=dex.bin");
+        println!("cargo:rerun-if-changed=battle.bin");
+    }
+
+    // Hash the bundles so that clients and servers can check they were built with the same data
+
+    let hash = |path: &str| match std::fs::read(path) {
+        Ok(data) => hash::hash(&data),
+        Err(err) => {
+            println!("cargo:warning=Could not read {} to hash with error {}", path, err);
+            0
+        }
+    };
+
+    let hashes = format!(
+        concat!(
+            "/// Hash of the dex bundle this crate was built with.\n",
+            "pub const DEX_HASH: u64 = {};\n",
+            "/// Hash of the battle move bundle this crate was built with.\n",
+            "pub const BATTLE_HASH: u64 = {};\n",
+        ),
+        hash("dex.bin"),
+        hash("battle.bin"),
+    );
+
+    let output = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("bundles.rs");
+
+    std::fs::write(&output, hashes).unwrap_or_else(|err| {
+        panic!(
+            "Cannot create / write to bundle hash file at {:?} with error {}",
+            output, err
+        )
+    });
+}
@@ -0,0 +1,131 @@
+use std::collections::VecDeque;
+
+use common::chat::{ChatError, ChatMessage, ChatScope, MAX_CHAT_LENGTH};
+
+use crate::{
+    engine::{
+        graphics::{draw_text_left, DrawParams},
+        input::{self, keyboard::Key},
+        text::TextColor,
+        util::HEIGHT,
+    },
+    GameContext,
+};
+
+/// Lines kept in the scrollback.
+const SCROLLBACK: usize = 50;
+/// Lines shown at once.
+const SHOWN: usize = 8;
+/// Characters that fit on one line of the screen.
+const LINE_WIDTH: usize = 38;
+
+/// Chat scrollback and the message being typed, shown over the lobby or battle.
+#[derive(Default)]
+pub struct Chat {
+    lines: VecDeque<String>,
+    input: String,
+    /// If the overlay is shown and typing goes to the chat.
+    pub open: bool,
+    /// Lines the overlay is scrolled up by.
+    scroll: usize,
+    /// Lines received while the overlay was closed.
+    unread: usize,
+}
+
+impl Chat {
+    pub fn receive(&mut self, message: ChatMessage) {
+        let scope = match message.scope {
+            ChatScope::Room => "room",
+            ChatScope::Battle => "battle",
+        };
+        self.push(&format!("[{}] {}: {}", scope, message.from, message.text));
+    }
+
+    pub fn rejected(&mut self, err: ChatError) {
+        self.push(&format!("* {}", err));
+    }
+
+    /// Toggle the overlay with Tab and handle typing, returning a message to send.
+    pub fn update(&mut self, ctx: &GameContext) -> Option<String> {
+        if input::keyboard::is_key_pressed(ctx, Key::Tab) {
+            self.open = !self.open;
+            self.scroll = 0;
+            self.unread = 0;
+        }
+        if !self.open {
+            return None;
+        }
+
+        while let Some(c) = crate::engine::inner::prelude::get_char_pressed() {
+            if !c.is_control() && self.input.chars().count() < MAX_CHAT_LENGTH {
+                self.input.push(c);
+            }
+        }
+        if input::keyboard::is_key_pressed(ctx, Key::Backspace) {
+            self.input.pop();
+        }
+        if input::keyboard::is_key_pressed(ctx, Key::Up) {
+            self.scroll = (self.scroll + 1).min(self.lines.len().saturating_sub(SHOWN));
+        }
+        if input::keyboard::is_key_pressed(ctx, Key::Down) {
+            self.scroll = self.scroll.saturating_sub(1);
+        }
+        if input::keyboard::is_key_pressed(ctx, Key::Escape) {
+            self.open = false;
+        } else if input::keyboard::is_key_pressed(ctx, Key::Enter) && !self.input.trim().is_empty()
+        {
+            self.scroll = 0;
+            return Some(std::mem::take(&mut self.input));
+        }
+        None
+    }
+
+    pub fn draw(&self, ctx: &mut GameContext) {
+        let params = DrawParams::color(TextColor::White.into());
+        if !self.open {
+            if self.unread > 0 {
+                let text = format!("{} new message(s), Tab to chat", self.unread);
+                draw_text_left(&mut ctx.engine, &1, &text, 5.0, HEIGHT - 15.0, params);
+            }
+            return;
+        }
+
+        let end = self.lines.len().saturating_sub(self.scroll);
+        let start = end.saturating_sub(SHOWN);
+        let bottom = HEIGHT - 30.0;
+        for (index, line) in self.lines.range(start..end).rev().enumerate() {
+            draw_text_left(
+                &mut ctx.engine,
+                &1,
+                line,
+                5.0,
+                bottom - index as f32 * 12.0,
+                params,
+            );
+        }
+
+        // Show the end of the input if it does not fit
+        let skip = self.input.chars().count().saturating_sub(LINE_WIDTH - 2);
+        let input = format!("> {}", self.input.chars().skip(skip).collect::<String>());
+        draw_text_left(&mut ctx.engine, &1, &input, 5.0, HEIGHT - 15.0, params);
+    }
+
+    fn push(&mut self, line: &str) {
+        let chars = line.chars().collect::<Vec<_>>();
+        for chunk in chars.chunks(LINE_WIDTH) {
+            self.lines.push_back(chunk.iter().collect());
+            if self.open {
+                // Keep the same lines in view while scrolled up
+                if self.scroll > 0 {
+                    self.scroll += 1;
+                }
+            } else {
+                self.unread += 1;
+            }
+        }
+        while self.lines.len() > SCROLLBACK {
+            self.lines.pop_front();
+        }
+        self.scroll = self.scroll.min(self.lines.len().saturating_sub(SHOWN));
+    }
+}
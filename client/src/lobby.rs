@@ -0,0 +1,387 @@
+use serde::Serialize;
+
+use common::{
+    account::PlayerStats,
+    lobby::{BattleFormat, LobbyMessage, LobbyRequest, RoomId, RoomInfo, RoomSettings, RoomState},
+    ranking::{LeaderboardEntry, RankingMessage, RankingRequest},
+    tournament::{Bracket, MatchOutcome, TournamentFormat},
+    NetClientMessage,
+};
+
+use crate::{
+    engine::{
+        graphics::{draw_text_left, DrawParams},
+        input::{self, keyboard::Key},
+        text::TextColor,
+    },
+    sender::BattleConnection,
+    ConnectState, GameContext,
+};
+
+/// Room list and the room the player is in, shown after joining a server.
+#[derive(Default)]
+pub struct Lobby {
+    /// Name the player joined with.
+    name: String,
+    /// Record of the player's account, if they have one.
+    stats: Option<PlayerStats>,
+    /// Ranked rating of the player's account, if they have one.
+    rating: Option<f64>,
+    /// Highest rated players, shown instead of the rooms while it is open.
+    leaderboard: Option<Vec<LeaderboardEntry>>,
+    /// Bracket of the tournament the server is running, shown instead of the rooms.
+    tournament: Option<Bracket>,
+    rooms: Vec<RoomInfo>,
+    selected: usize,
+    room: Option<RoomState>,
+    /// Text being typed and what it is for.
+    input: Option<(Input, String)>,
+    /// Last thing the server told us, such as an error.
+    status: Option<String>,
+    /// If the room list should be requested again.
+    refresh: bool,
+}
+
+enum Input {
+    /// Name, format and password of a new room
+    Create,
+    /// Password of a room to join
+    Password(RoomId),
+}
+
+impl Lobby {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            refresh: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn stats(&mut self, name: String, stats: Option<PlayerStats>) {
+        if name == self.name {
+            self.stats = stats;
+        }
+    }
+
+    pub(crate) fn ranking(&mut self, message: RankingMessage) {
+        match message {
+            RankingMessage::Leaderboard(entries) => self.leaderboard = Some(entries),
+            RankingMessage::History(name, rating) => {
+                if name == self.name {
+                    self.rating = rating.map(|rating| rating.rating);
+                }
+            }
+            RankingMessage::Updated(change) => self.rating = Some(change.rating),
+        }
+    }
+
+    pub fn tournament(&mut self, bracket: Bracket) {
+        self.tournament = Some(bracket);
+    }
+
+    /// Handle a lobby message from the server, returning a new state if the player leaves the lobby.
+    pub(crate) fn receive(&mut self, message: LobbyMessage) -> Option<ConnectState> {
+        match message {
+            LobbyMessage::Rooms(rooms) => {
+                self.selected = self.selected.min(rooms.len().saturating_sub(1));
+                self.rooms = rooms;
+            }
+            LobbyMessage::Room(room) => {
+                self.status = None;
+                self.room = Some(room);
+            }
+            LobbyMessage::Left => {
+                self.room = None;
+                self.refresh = true;
+            }
+            LobbyMessage::Kicked => {
+                self.room = None;
+                self.refresh = true;
+                self.status = Some("Kicked from the room".to_owned());
+            }
+            LobbyMessage::Queued => return Some(ConnectState::ConnectedWait),
+            LobbyMessage::Error(err) => self.status = Some(err.to_string()),
+        }
+        None
+    }
+
+    pub fn update<ID: Serialize>(
+        &mut self,
+        ctx: &mut GameContext,
+        connection: &mut BattleConnection,
+    ) {
+        if std::mem::take(&mut self.refresh) {
+            self.request::<ID>(connection, LobbyRequest::Rooms);
+        }
+
+        let typed = std::iter::from_fn(crate::engine::inner::prelude::get_char_pressed)
+            .filter(|c| !c.is_control())
+            .collect::<String>();
+
+        if let Some((input, text)) = &mut self.input {
+            text.push_str(&typed);
+            if input::keyboard::is_key_pressed(ctx, Key::Backspace) {
+                text.pop();
+            }
+            if input::keyboard::is_key_pressed(ctx, Key::Escape) {
+                self.input = None;
+            } else if input::keyboard::is_key_pressed(ctx, Key::Enter) {
+                let request = match input {
+                    Input::Create => {
+                        let mut words = text.split_ascii_whitespace();
+                        let name = words.next().unwrap_or_default().to_owned();
+                        match words.next().map(str::parse::<BattleFormat>) {
+                            Some(Err(err)) => {
+                                self.status = Some(err);
+                                None
+                            }
+                            format => Some(LobbyRequest::Create(RoomSettings {
+                                name,
+                                format: format
+                                    .and_then(Result::ok)
+                                    .unwrap_or(BattleFormat::Singles),
+                                password: words.next().map(ToOwned::to_owned),
+                            })),
+                        }
+                    }
+                    Input::Password(id) => Some(LobbyRequest::Join(*id, Some(text.clone()))),
+                };
+                self.input = None;
+                if let Some(request) = request {
+                    self.request::<ID>(connection, request);
+                }
+            }
+            return;
+        }
+
+        if self.leaderboard.is_some() {
+            if input::keyboard::is_key_pressed(ctx, Key::Escape)
+                || typed.chars().any(|c| c.eq_ignore_ascii_case(&'l'))
+            {
+                self.leaderboard = None;
+            }
+            return;
+        }
+
+        if self.tournament.is_some() {
+            if typed.chars().any(|c| c.eq_ignore_ascii_case(&'l')) {
+                connection.send(&NetClientMessage::<ID>::Ranking(
+                    RankingRequest::Leaderboard,
+                ));
+            }
+            return;
+        }
+
+        match &self.room {
+            Some(room) => {
+                if input::keyboard::is_key_pressed(ctx, Key::Space) {
+                    let ready = room
+                        .members
+                        .get(room.you as usize)
+                        .map(|member| member.ready)
+                        .unwrap_or_default();
+                    self.request::<ID>(connection, LobbyRequest::Ready(!ready));
+                } else if input::keyboard::is_key_pressed(ctx, Key::Escape) {
+                    self.request::<ID>(connection, LobbyRequest::Leave);
+                } else if room.owner == room.you {
+                    // Members are numbered from 1 on screen
+                    if let Some(index) = typed
+                        .chars()
+                        .find_map(|c| c.to_digit(10))
+                        .and_then(|digit| digit.checked_sub(1))
+                    {
+                        self.request::<ID>(connection, LobbyRequest::Kick(index as _));
+                    }
+                }
+            }
+            None => {
+                if input::keyboard::is_key_pressed(ctx, Key::Up) {
+                    self.selected = self.selected.saturating_sub(1);
+                }
+                if input::keyboard::is_key_pressed(ctx, Key::Down) {
+                    self.selected = (self.selected + 1).min(self.rooms.len().saturating_sub(1));
+                }
+                if input::keyboard::is_key_pressed(ctx, Key::Enter) {
+                    if let Some(room) = self.rooms.get(self.selected) {
+                        match room.locked {
+                            true => self.input = Some((Input::Password(room.id), String::new())),
+                            false => {
+                                let request = LobbyRequest::Join(room.id, None);
+                                self.request::<ID>(connection, request);
+                            }
+                        }
+                    }
+                }
+                for c in typed.chars() {
+                    match c.to_ascii_lowercase() {
+                        'c' => self.input = Some((Input::Create, String::new())),
+                        'r' => self.refresh = true,
+                        'q' => self.request::<ID>(connection, LobbyRequest::QuickMatch),
+                        'k' => self.request::<ID>(connection, LobbyRequest::RankedMatch),
+                        'l' => connection.send(&NetClientMessage::<ID>::Ranking(
+                            RankingRequest::Leaderboard,
+                        )),
+                        _ => (),
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn draw(&self, ctx: &mut GameContext) {
+        let params = DrawParams::color(TextColor::White.into());
+        let mut lines = Vec::new();
+
+        match (&self.input, &self.room) {
+            _ if self.leaderboard.is_some() => {
+                lines.push("Leaderboard".to_owned());
+                for (index, entry) in self.leaderboard.iter().flatten().enumerate() {
+                    lines.push(format!(
+                        "{}. {} {:.0} ({} battles)",
+                        index + 1,
+                        entry.name,
+                        entry.rating,
+                        entry.battles
+                    ));
+                }
+                lines.push("L or Escape: back".to_owned());
+            }
+            _ if self.tournament.is_some() => {
+                if let Some(bracket) = &self.tournament {
+                    self.draw_bracket(bracket, &mut lines);
+                }
+            }
+            (Some((input, text)), ..) => {
+                lines.push(
+                    match input {
+                        Input::Create => "New room: name format password",
+                        Input::Password(..) => "Room password:",
+                    }
+                    .to_owned(),
+                );
+                lines.push(text.clone());
+                if let Input::Create = input {
+                    lines.push("Formats: singles doubles triples ffa".to_owned());
+                }
+                lines.push("Enter to confirm, Escape to cancel".to_owned());
+            }
+            (None, Some(room)) => {
+                lines.push(format!("{} ({})", room.info.name, room.info.format));
+                for (index, member) in room.members.iter().enumerate() {
+                    lines.push(format!(
+                        "{}. {}{}{}",
+                        index + 1,
+                        member.name,
+                        if index == room.owner as usize {
+                            " (owner)"
+                        } else {
+                            ""
+                        },
+                        if member.ready { " - ready" } else { "" },
+                    ));
+                }
+                lines.push(format!(
+                    "{} of {} players",
+                    room.info.players, room.info.capacity
+                ));
+                lines.push("Space: ready, Escape: leave".to_owned());
+                if room.owner == room.you {
+                    lines.push("Number: kick player".to_owned());
+                }
+            }
+            (None, None) => {
+                lines.push(match (&self.stats, self.rating) {
+                    (Some(stats), rating) => format!(
+                        "{}: {} W {} L {} D, rated {:.0}",
+                        self.name,
+                        stats.wins,
+                        stats.losses,
+                        stats.draws,
+                        rating.unwrap_or_default()
+                    ),
+                    (None, ..) => self.name.clone(),
+                });
+                lines.push("Rooms".to_owned());
+                if self.rooms.is_empty() {
+                    lines.push("No rooms yet".to_owned());
+                }
+                for (index, room) in self.rooms.iter().enumerate() {
+                    lines.push(format!(
+                        "{}{} ({}) {}/{}{}",
+                        if index == self.selected { "> " } else { "  " },
+                        room.name,
+                        room.format,
+                        room.players,
+                        room.capacity,
+                        if room.locked { " locked" } else { "" },
+                    ));
+                }
+                lines.push("Enter: join, C: create".to_owned());
+                lines.push("R: refresh, Q: quick match".to_owned());
+                lines.push("K: ranked match, L: leaderboard".to_owned());
+            }
+        }
+
+        if let Some(status) = &self.status {
+            lines.push(status.clone());
+        }
+
+        for (index, line) in lines.iter().enumerate() {
+            draw_text_left(
+                &mut ctx.engine,
+                &1,
+                line,
+                5.0,
+                5.0 + index as f32 * 15.0,
+                params,
+            );
+        }
+    }
+
+    /// Lines showing the current round of a tournament and its standings.
+    fn draw_bracket(&self, bracket: &Bracket, lines: &mut Vec<String>) {
+        lines.push(match &bracket.winner {
+            Some(winner) => format!("Tournament won by {}", winner),
+            None => format!(
+                "Tournament round {} of {}",
+                bracket.rounds.len(),
+                bracket.total_rounds
+            ),
+        });
+        for game in bracket.rounds.last().into_iter().flatten() {
+            let players = game.players.join(" vs ");
+            lines.push(match &game.outcome {
+                Some(MatchOutcome::Won(winner)) => format!("{}: {} won", players, winner),
+                Some(MatchOutcome::Drew) => format!("{}: draw", players),
+                Some(MatchOutcome::NoShow(Some(winner))) => {
+                    format!("{}: {} won, no-show", players, winner)
+                }
+                Some(MatchOutcome::NoShow(None)) => format!("{}: no-show", players),
+                Some(MatchOutcome::Bye) => format!("{}: bye", players),
+                None => format!("{}: playing", players),
+            });
+        }
+        let standings = bracket
+            .standings
+            .iter()
+            .filter(|standing| !standing.eliminated)
+            .map(|standing| match bracket.format {
+                TournamentFormat::SingleElimination => standing.name.clone(),
+                TournamentFormat::Swiss => format!("{} {}", standing.name, standing.points),
+            })
+            .collect::<Vec<_>>();
+        lines.push(match bracket.format {
+            TournamentFormat::SingleElimination => format!("Still in: {}", standings.join(", ")),
+            TournamentFormat::Swiss => format!("Standings: {}", standings.join(", ")),
+        });
+        if bracket.winner.is_none() {
+            lines.push(format!("{}, waiting for your next match", self.name));
+        }
+        lines.push("L: leaderboard".to_owned());
+    }
+
+    fn request<ID: Serialize>(&self, connection: &mut BattleConnection, request: LobbyRequest) {
+        connection.send(&NetClientMessage::<ID>::Lobby(request));
+    }
+}
@@ -3,7 +3,7 @@
 extern crate firecore_battle_gui as gui;
 extern crate firecore_battle_net as common;
 pub use gui::pokedex::engine;
-use rand::prelude::SmallRng;
+use rand::{prelude::SmallRng, Rng};
 use serde::{de::DeserializeOwned, Serialize};
 
 use std::{
@@ -15,10 +15,17 @@ use std::{
 };
 
 use common::{
+    account::LoginError,
     battle::endpoint::MpscEndpoint,
     deserialize,
-    pokedex::{item::Item, moves::Move, pokemon::Pokemon, BasicDex},
-    Id, DEFAULT_PORT,
+    hash::hash,
+    pokedex::{
+        item::Item,
+        moves::Move,
+        pokemon::{owned::SavedPokemon, party::Party as SavedParty, Pokemon},
+        BasicDex,
+    },
+    BundleHash, Id, ProtocolRange, DEFAULT_PORT, PROTOCOL_VERSION,
 };
 
 use engine::{
@@ -48,10 +55,14 @@ use gui::{
 
 use gui::BattlePlayerGui;
 
-use self::sender::BattleConnection;
+use self::{lobby::Lobby, rematch::Rematch, replay::ReplayViewer, sender::BattleConnection};
 
+mod chat;
+mod lobby;
+mod rematch;
+mod replay;
 mod sender;
-mod net;
+mod teams;
 
 const SCALE: f32 = 3.0;
 const TITLE: &str = "Pokemon Battle";
@@ -62,6 +73,9 @@ static mut MOVEDEX: Option<BasicDex<Move>> = None;
 
 static mut ITEMDEX: Option<BasicDex<Item>> = None;
 
+/// Hashes of the data bundles the client is running with, sent to the server when joining.
+static mut BUNDLES: BundleHash = BundleHash::BUILT;
+
 fn main() {
     // let l = simple_logger::SimpleLogger::new();
 
@@ -77,13 +91,11 @@ fn main() {
     let fonts: Vec<FontSheet<Vec<u8>>> = deserialize(include_bytes!("../fonts.bin"))
         .unwrap_or_else(|err| panic!("Could not read fonts with error {}", err));
 
-    let (pokedex, movedex, itemdex) =
-        deserialize::<(BasicDex<Pokemon>, BasicDex<Move>, BasicDex<Item>)>(include_bytes!(
-            "../../dex.bin"
-        ))
-        .unwrap_or_else(|err| panic!("Could not read pokedex with error {}", err));
+    let ((pokedex, movedex, itemdex), dex_hash) = load_dex();
 
     unsafe {
+        BUNDLES.dex = dex_hash;
+        BUNDLES.battle = battle_hash();
         POKEDEX = Some(pokedex);
         MOVEDEX = Some(movedex);
         ITEMDEX = Some(itemdex);
@@ -150,12 +162,14 @@ impl DerefMut for GameContext {
     }
 }
 
-enum States {
+enum States<ID> {
     Connect(String),
     Connected(BattleConnection, ConnectState),
+    /// Watching a recorded battle
+    Replay(ReplayViewer<ID>),
 }
 
-impl States {
+impl<ID> States<ID> {
     pub const CONNECT: Self = Self::Connect(String::new());
 }
 
@@ -164,9 +178,19 @@ enum ConnectState {
     WaitConfirm,
     // WaitBegin,
     Closed,
-    WrongVersion(f32),
+    /// Server does not support this client's protocol, with the versions it does support if known
+    WrongVersion(Option<ProtocolRange>, f32),
+    WrongBundles(f32),
+    TeamRejected(Vec<String>, f32),
+    LoginFailed(LoginError, f32),
+    /// Browsing rooms or waiting in one
+    Lobby(Lobby),
+    /// Voting on a rematch after a battle
+    Rematch(Rematch),
     ConnectedWait,
     ConnectedPlay,
+    /// Watching a battle without playing in it
+    Spectating,
 }
 
 impl Default for ConnectState {
@@ -179,7 +203,7 @@ struct GameState<
     'd,
     ID: Default + Clone + Debug + Eq + Hash + Serialize + DeserializeOwned + Send + 'static,
 > {
-    state: States,
+    state: States<ID>,
     player: GuiPlayer<'d>,
     gui: BattlePlayerGui<ID, &'d Pokemon, &'d Move, &'d Item>,
     gui_endpoint: MpscEndpoint<ID>,
@@ -232,12 +256,21 @@ impl<
 
 impl<
         'd,
-        ID: Default + Clone + Debug + Eq + Hash + Serialize + DeserializeOwned + Send + 'static,
+        ID: Default
+            + Clone
+            + Debug
+            + Eq
+            + Hash
+            + From<u8>
+            + Serialize
+            + DeserializeOwned
+            + Send
+            + 'static,
     > State<GameContext> for GameState<'d, ID>
 {
     fn end(&mut self, _ctx: &mut GameContext) {
         match &mut self.state {
-            States::Connect(..) => (),
+            States::Connect(..) | States::Replay(..) => (),
             States::Connected(connection, ..) => {
                 self.gui.forfeit();
                 connection.end::<ID>();
@@ -252,16 +285,53 @@ impl<
                     string.pop();
                 }
                 if input::keyboard::is_key_pressed(ctx, Key::Enter) {
-                    let mut strings = string.split_ascii_whitespace();
+                    let mut strings = string.split_ascii_whitespace().peekable();
+                    if strings.next_if_eq(&"replay").is_some() {
+                        match strings.next().and_then(ReplayViewer::load) {
+                            Some((viewer, party)) => {
+                                info!("Playing replay");
+                                sender::set_party(ctx, &mut self.player, party);
+                                self.state = States::Replay(viewer);
+                            }
+                            None => string.clear(),
+                        }
+                        return;
+                    }
+                    let spectate = strings.next_if_eq(&"spectate").is_some();
                     match strings.next() {
                         Some(addr) => match find_address(parse_address(addr)) {
                             Ok(addr) => {
                                 info!("Connecting to server at {}", addr);
-                                match BattleConnection::connect(
-                                    addr,
-                                    strings.next().map(ToOwned::to_owned),
-                                    // strings.next().map(|s| s.parse().ok()).flatten(),
-                                ) {
+                                let session = ctx.random.gen();
+                                let connection = match spectate {
+                                    true => BattleConnection::spectate::<ID>(
+                                        addr,
+                                        session,
+                                        strings.next().and_then(|id| id.parse().ok()),
+                                    ),
+                                    false => {
+                                        // Names can be followed by a password to log in with
+                                        let (name, password) = match strings.next() {
+                                            Some(name) => match name.split_once(':') {
+                                                Some((name, password)) => {
+                                                    (Some(name), Some(password.to_owned()))
+                                                }
+                                                None => (Some(name), None),
+                                            },
+                                            None => (None, None),
+                                        };
+                                        let team = strings.next().and_then(load_team);
+                                        BattleConnection::connect::<ID>(
+                                            addr,
+                                            session,
+                                            name.map(ToOwned::to_owned),
+                                            password,
+                                            team,
+                                            // strings.next().map(|s| s.parse().ok()).flatten(),
+                                        )
+                                    }
+                                };
+                                match connection {
                                     Some(connection) => {
                                         self.state =
                                             States::Connected(connection, ConnectState::default())
@@ -288,56 +358,125 @@ impl<
                     }
                 }
             }
-            States::Connected(connection, state) => match state {
-                // ConnectState::WaitConnect => {
-                //     if connection.connected() {
-                //         *state = ConnectState::WaitConfirm;
-                //     }
-                // }
-                ConnectState::WaitConfirm => {
-                    if let Some(connected) =
-                        connection.wait_confirm::<ID>(ctx, &mut self.player, delta)
-                    {
-                        *state = connected;
+            States::Connected(connection, state) => {
+                connection.update::<ID>(delta);
+                match state {
+                    // ConnectState::WaitConnect => {
+                    //     if connection.connected() {
+                    //         *state = ConnectState::WaitConfirm;
+                    //     }
+                    // }
+                    ConnectState::WaitConfirm => {
+                        if let Some(connected) =
+                            connection.wait_confirm::<ID>(ctx, &mut self.player)
+                        {
+                            *state = connected;
+                        }
                     }
-                }
-                ConnectState::Closed => self.state = States::Connect(String::new()),
-                ConnectState::ConnectedWait => connection.gui_receive(
-                    &mut self.gui,
-                    &mut self.player,
-                    &mut self.gui_endpoint,
-                    ctx,
-                    state,
-                ),
-                ConnectState::WrongVersion(remaining) => {
-                    *remaining -= delta;
-                    if remaining < &mut 0.0 {
-                        self.state = States::Connect(String::new());
+                    ConnectState::Closed => self.state = States::Connect(String::new()),
+                    ConnectState::Lobby(..) => {
+                        connection.gui_receive(
+                            &mut self.gui,
+                            &mut self.player,
+                            &mut self.gui_endpoint,
+                            ctx,
+                            state,
+                        );
+                        if let ConnectState::Lobby(lobby) = state {
+                            if !connection.update_chat::<ID>(ctx) {
+                                lobby.update::<ID>(ctx, connection);
+                            }
+                        }
                     }
-                }
-                ConnectState::ConnectedPlay => {
-                    connection.gui_receive(
+                    ConnectState::Rematch(..) => {
+                        connection.gui_receive(
+                            &mut self.gui,
+                            &mut self.player,
+                            &mut self.gui_endpoint,
+                            ctx,
+                            state,
+                        );
+                        if let ConnectState::Rematch(rematch) = state {
+                            if let Some(next) = rematch.update::<ID>(ctx, connection, delta) {
+                                *state = next;
+                            }
+                        }
+                    }
+                    ConnectState::ConnectedWait => connection.gui_receive(
                         &mut self.gui,
                         &mut self.player,
                         &mut self.gui_endpoint,
                         ctx,
                         state,
+                    ),
+                    ConnectState::WrongVersion(.., remaining)
+                    | ConnectState::WrongBundles(remaining)
+                    | ConnectState::TeamRejected(.., remaining)
+                    | ConnectState::LoginFailed(.., remaining) => {
+                        *remaining -= delta;
+                        if remaining < &mut 0.0 {
+                            self.state = States::Connect(String::new());
+                        }
+                    }
+                    ConnectState::ConnectedPlay | ConnectState::Spectating => {
+                        let spectating = matches!(state, ConnectState::Spectating);
+                        connection.gui_receive(
+                            &mut self.gui,
+                            &mut self.player,
+                            &mut self.gui_endpoint,
+                            ctx,
+                            state,
+                        );
+                        // The battle waits while the player is typing in the chat
+                        if !connection.update_chat::<ID>(ctx) {
+                            let pokedex = unsafe { crate::POKEDEX.as_ref().unwrap() };
+                            let movedex = unsafe { crate::MOVEDEX.as_ref().unwrap() };
+                            let itemdex = unsafe { crate::ITEMDEX.as_ref().unwrap() };
+                            self.gui.update(
+                                &ctx.engine,
+                                &ctx.dex,
+                                pokedex,
+                                movedex,
+                                itemdex,
+                                delta,
+                                &mut self.player.bag,
+                            );
+                        }
+                        match spectating {
+                            true => sender::gui_discard(&mut self.gui_endpoint),
+                            false => connection.gui_send(&mut self.gui_endpoint),
+                        }
+                    }
+                }
+            }
+            States::Replay(viewer) => {
+                if input::keyboard::is_key_pressed(ctx, Key::Escape) {
+                    self.state = States::CONNECT;
+                    return;
+                }
+                for message in viewer.update(ctx, delta) {
+                    sender::gui_process(
+                        &mut self.gui,
+                        &mut self.player,
+                        &mut self.gui_endpoint,
+                        ctx,
+                        message,
                     );
-                    let pokedex = unsafe { crate::POKEDEX.as_ref().unwrap() };
-                    let movedex = unsafe { crate::MOVEDEX.as_ref().unwrap() };
-                    let itemdex = unsafe { crate::ITEMDEX.as_ref().unwrap() };
-                    self.gui.update(
-                        &ctx.engine,
-                        &ctx.dex,
-                        pokedex,
-                        movedex,
-                        itemdex,
-                        delta,
-                        &mut self.player.bag,
-                    );
-                    connection.gui_send(&mut self.gui_endpoint);
                 }
-            },
+                let pokedex = unsafe { crate::POKEDEX.as_ref().unwrap() };
+                let movedex = unsafe { crate::MOVEDEX.as_ref().unwrap() };
+                let itemdex = unsafe { crate::ITEMDEX.as_ref().unwrap() };
+                self.gui.update(
+                    &ctx.engine,
+                    &ctx.dex,
+                    pokedex,
+                    movedex,
+                    itemdex,
+                    delta,
+                    &mut self.player.bag,
+                );
+                sender::gui_discard(&mut self.gui_endpoint);
+            }
         }
     }
 
@@ -372,6 +511,51 @@ impl<
                     65.0,
                     params,
                 );
+                draw_text_left(
+                    &mut ctx.engine,
+                    &1,
+                    "Format: address name team.ron",
+                    5.0,
+                    85.0,
+                    params,
+                );
+                draw_text_left(
+                    &mut ctx.engine,
+                    &1,
+                    "Or: spectate address battle",
+                    5.0,
+                    105.0,
+                    params,
+                );
+                draw_text_left(
+                    &mut ctx.engine,
+                    &1,
+                    "Or: replay file.replay",
+                    5.0,
+                    125.0,
+                    params,
+                );
+            }
+            States::Replay(viewer) => {
+                self.gui.draw(
+                    &mut ctx.engine,
+                    &ctx.dex,
+                    &self.player.party,
+                    &self.player.bag,
+                );
+                let status = match (viewer.finished(), viewer.paused) {
+                    (true, _) => "Replay finished, Escape to exit".to_owned(),
+                    (false, true) => format!("Replay paused x{}", viewer.speed),
+                    (false, false) => format!("Replay x{}", viewer.speed),
+                };
+                draw_text_left(
+                    &mut ctx.engine,
+                    &1,
+                    &status,
+                    5.0,
+                    5.0,
+                    DrawParams::color(TextColor::White.into()),
+                );
             }
             States::Connected(.., connected) => match connected {
                 ConnectState::WaitConfirm => draw_text_left(
@@ -382,6 +566,7 @@ impl<
                     5.0,
                     DrawParams::color(TextColor::White.into()),
                 ),
+                ConnectState::Lobby(lobby) => lobby.draw(ctx),
                 ConnectState::ConnectedWait => {
                     draw_text_left(
                         &mut ctx.engine,
@@ -400,14 +585,76 @@ impl<
                         DrawParams::color(TextColor::White.into()),
                     );
                 }
-                ConnectState::WrongVersion(..) => draw_text_left(
+                ConnectState::WrongVersion(range, ..) => {
+                    let params = DrawParams::color(TextColor::White.into());
+                    draw_text_left(
+                        &mut ctx.engine,
+                        &1,
+                        "Server version is incompatible!",
+                        5.0,
+                        25.0,
+                        params,
+                    );
+                    if let Some(range) = range {
+                        let upgrade = match PROTOCOL_VERSION < range.min {
+                            true => "Upgrade",
+                            false => "Downgrade",
+                        };
+                        draw_text_left(
+                            &mut ctx.engine,
+                            &1,
+                            &format!("{} to version {}", upgrade, range.version),
+                            5.0,
+                            45.0,
+                            params,
+                        );
+                        draw_text_left(
+                            &mut ctx.engine,
+                            &1,
+                            &format!(
+                                "Protocol {}, server supports {} to {}",
+                                PROTOCOL_VERSION, range.min, range.max
+                            ),
+                            5.0,
+                            65.0,
+                            params,
+                        );
+                    }
+                }
+                ConnectState::WrongBundles(..) => draw_text_left(
                     &mut ctx.engine,
                     &1,
-                    "Server version is incompatible!",
+                    "Game data is different from the server's!",
                     5.0,
                     25.0,
                     DrawParams::color(TextColor::White.into()),
                 ),
+                ConnectState::TeamRejected(problems, ..) => {
+                    let params = DrawParams::color(TextColor::White.into());
+                    draw_text_left(
+                        &mut ctx.engine,
+                        &1,
+                        "Team was not accepted by the server!",
+                        5.0,
+                        5.0,
+                        params,
+                    );
+                    for (index, problem) in problems.iter().enumerate() {
+                        draw_text_left(
+                            &mut ctx.engine,
+                            &1,
+                            problem,
+                            5.0,
+                            25.0 + index as f32 * 15.0,
+                            params,
+                        );
+                    }
+                }
+                ConnectState::LoginFailed(err, ..) => {
+                    let params = DrawParams::color(TextColor::White.into());
+                    draw_text_left(&mut ctx.engine, &1, "Could not log in!", 5.0, 5.0, params);
+                    draw_text_left(&mut ctx.engine, &1, &err.to_string(), 5.0, 25.0, params);
+                }
                 ConnectState::ConnectedPlay => {
                     self.gui.draw(
                         &mut ctx.engine,
@@ -416,6 +663,31 @@ impl<
                         &self.player.bag,
                     );
                 }
+                ConnectState::Rematch(rematch) => {
+                    self.gui.draw(
+                        &mut ctx.engine,
+                        &ctx.dex,
+                        &self.player.party,
+                        &self.player.bag,
+                    );
+                    rematch.draw(ctx);
+                }
+                ConnectState::Spectating => {
+                    self.gui.draw(
+                        &mut ctx.engine,
+                        &ctx.dex,
+                        &self.player.party,
+                        &self.player.bag,
+                    );
+                    draw_text_left(
+                        &mut ctx.engine,
+                        &1,
+                        "Spectating",
+                        5.0,
+                        5.0,
+                        DrawParams::color(TextColor::White.into()),
+                    );
+                }
                 ConnectState::Closed => draw_text_left(
                     &mut ctx.engine,
                     &1,
@@ -426,6 +698,61 @@ impl<
                 ),
             },
         }
+        if let States::Connected(connection, ConnectState::ConnectedPlay) = &self.state {
+            if let Some(clock) = connection.clock() {
+                let params = DrawParams::color(TextColor::White.into());
+                if let Some(turn) = clock.turn {
+                    draw_text_left(
+                        &mut ctx.engine,
+                        &1,
+                        &format!("Time {}", turn.ceil()),
+                        WIDTH - 70.0,
+                        5.0,
+                        params,
+                    );
+                }
+                if let Some(total) = clock.total {
+                    draw_text_left(
+                        &mut ctx.engine,
+                        &1,
+                        &format!("Total {}", total.ceil()),
+                        WIDTH - 70.0,
+                        20.0,
+                        params,
+                    );
+                }
+            }
+        }
+        if let States::Connected(connection, state) = &self.state {
+            // Spectators have a label above
+            let y = match state {
+                ConnectState::ConnectedPlay => Some(5.0),
+                ConnectState::Spectating => Some(20.0),
+                _ => None,
+            };
+            if let (Some(y), Some(setup)) = (y, connection.setup()) {
+                teams::draw(ctx, setup, &self.gui, &self.player.party, y);
+            }
+        }
+        if let States::Connected(
+            connection,
+            ConnectState::Lobby(..) | ConnectState::ConnectedPlay | ConnectState::Spectating,
+        ) = &self.state
+        {
+            connection.chat.draw(ctx);
+        }
+        if let States::Connected(connection, ..) = &self.state {
+            if !connection.responding() {
+                draw_text_left(
+                    &mut ctx.engine,
+                    &1,
+                    "Server not responding",
+                    5.0,
+                    HEIGHT - 15.0,
+                    DrawParams::color(TextColor::White.into()),
+                );
+            }
+        }
         // graphics::reset_canvas(ctx);
         // graphics::clear(ctx, Color::BLACK);
         // self.scaler.draw(ctx);
@@ -440,39 +767,99 @@ impl<
     // }
 }
 
+type Dexes = (BasicDex<Pokemon>, BasicDex<Move>, BasicDex<Item>);
+
+/// Load the dexes from the file given with `--dex`, or use the built in ones.
+/// Also returns the hash of the bundle they were loaded from.
+fn load_dex() -> (Dexes, u64) {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        if let Some(path) = std::env::args().skip_while(|arg| arg != "--dex").nth(1) {
+            match std::fs::read(&path) {
+                Ok(bytes) => match deserialize::<Dexes>(&bytes) {
+                    Ok(dexes) => return (dexes, hash(&bytes)),
+                    Err(err) => warn!(
+                        "Could not read dex bundle at {} with error {}, using the built in one.",
+                        path, err
+                    ),
+                },
+                Err(err) => warn!(
+                    "Could not open dex bundle at {} with error {}, using the built in one.",
+                    path, err
+                ),
+            }
+        }
+    }
+    let bytes = include_bytes!("../../dex.bin");
+    let dexes = deserialize::<Dexes>(bytes)
+        .unwrap_or_else(|err| panic!("Could not read pokedex with error {}", err));
+    (dexes, hash(bytes))
+}
+
+/// Hash of the battle move bundle given with `--battle`, or of the built in one.
+/// The client does not run battles itself, but tells servers which moves it expects.
+fn battle_hash() -> u64 {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        if let Some(path) = std::env::args().skip_while(|arg| arg != "--battle").nth(1) {
+            match std::fs::read(&path) {
+                Ok(bytes) => return hash(&bytes),
+                Err(err) => warn!(
+                    "Could not open battle bundle at {} with error {}, using the built in one.",
+                    path, err
+                ),
+            }
+        }
+    }
+    BundleHash::BUILT.battle
+}
+
+/// Load a party from a RON file to submit to the server.
+fn load_team(path: &str) -> Option<SavedParty<SavedPokemon>> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        match std::fs::read_to_string(path) {
+            Ok(data) => match ron::from_str(&data) {
+                Ok(team) => return Some(team),
+                Err(err) => warn!("Could not parse team file at {} with error {}", path, err),
+            },
+            Err(err) => warn!("Could not read team file at {} with error {}", path, err),
+        }
+    }
+    #[cfg(target_arch = "wasm32")]
+    warn!("Cannot load team file at {} on this platform", path);
+    None
+}
+
 fn parse_address(addr: &str) -> (&str, u16) {
-    let mut parts = addr.split(':');
-    let addr = parts.next().unwrap();
-    let port = parts
-        .next()
-        .map(|port| port.parse::<u16>().ok())
-        .flatten()
+    // IPv6 addresses contain colons, so they need brackets to be given a port
+    let (addr, port) = match addr.strip_prefix('[').and_then(|addr| addr.split_once(']')) {
+        Some((addr, port)) => (addr, port.strip_prefix(':')),
+        None => match addr.parse::<std::net::Ipv6Addr>() {
+            Ok(..) => (addr, None),
+            Err(..) => match addr.rsplit_once(':') {
+                Some((addr, port)) => (addr, Some(port)),
+                None => (addr, None),
+            },
+        },
+    };
+    let port = port
+        .and_then(|port| port.parse::<u16>().ok())
         .unwrap_or(DEFAULT_PORT);
     (addr, port)
 }
 
 fn find_address(addr: (&str, u16)) -> Result<SocketAddr, std::io::Error> {
-    // use message_io::network::{RemoteAddr, ToRemoteAddr};
-    // match addr.to_remote_addr() {
-    //     Ok(address) => match address {
-    //         RemoteAddr::Socket(address) => Ok(address),
-    //         RemoteAddr::Str(..) => Err(std::io::Error::new(
-    //             std::io::ErrorKind::InvalidInput,
-    //             "The address was not able to be parsed.",
-    //         )),
-    //     },
-    //     Err(err) => Err(err),
-    // }
-    // #[cfg(not(target_arch = "wasm32"))]
-    // let ws = "";
-
-    use std::net::ToSocketAddrs;
-
-    Ok(format!("{}:{}", addr.0, addr.1).to_socket_addrs().unwrap().next().unwrap())
-    // Ok(SocketAddr::new(
-    //     addr.0
-    //         .parse()
-    //         .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?,
-    //     addr.1,
-    // ))
+    use std::net::{IpAddr, ToSocketAddrs};
+
+    if let Ok(ip) = addr.0.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, addr.1));
+    }
+
+    addr.to_socket_addrs()?.next().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "The address did not resolve to anything.",
+        )
+    })
 }
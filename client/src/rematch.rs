@@ -0,0 +1,145 @@
+use serde::Serialize;
+
+use common::{ranking::RatingChange, NetClientMessage, RematchMessage, RematchVote};
+
+use crate::{
+    engine::{
+        graphics::{draw_text_left, DrawParams},
+        input::{self, keyboard::Key},
+        text::TextColor,
+    },
+    sender::BattleConnection,
+    ConnectState, GameContext,
+};
+
+/// Voting on a rematch after a battle has ended.
+pub struct Rematch {
+    /// Seconds left to vote, once the server has offered a rematch.
+    remaining: Option<f32>,
+    votes: Vec<Option<bool>>,
+    voted: bool,
+    same_team: bool,
+    /// How the player's rating changed, if the battle was ranked.
+    pub rating: Option<RatingChange>,
+}
+
+impl Default for Rematch {
+    fn default() -> Self {
+        Self {
+            remaining: None,
+            votes: Vec::new(),
+            voted: false,
+            same_team: true,
+            rating: None,
+        }
+    }
+}
+
+impl Rematch {
+    /// Handle a rematch message from the server, returning a new state if voting is over.
+    pub(crate) fn receive(&mut self, message: RematchMessage) -> Option<ConnectState> {
+        match message {
+            RematchMessage::Offer(time) => self.remaining = Some(time),
+            RematchMessage::Votes(votes) => self.votes = votes,
+            RematchMessage::Cancelled => return Some(ConnectState::Closed),
+            // The new battle begins right after
+            RematchMessage::Starting(..) => return Some(ConnectState::ConnectedWait),
+        }
+        None
+    }
+
+    pub(crate) fn update<ID: Serialize>(
+        &mut self,
+        ctx: &GameContext,
+        connection: &mut BattleConnection,
+        delta: f32,
+    ) -> Option<ConnectState> {
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining = (*remaining - delta).max(0.0);
+        }
+
+        let typed =
+            std::iter::from_fn(crate::engine::inner::prelude::get_char_pressed).collect::<String>();
+
+        if input::keyboard::is_key_pressed(ctx, Key::Escape) {
+            if self.ranked() {
+                return Some(ConnectState::Lobby(connection.lobby()));
+            }
+            if self.remaining.is_some() && !self.voted {
+                self.vote::<ID>(connection, false);
+            }
+            return Some(ConnectState::Closed);
+        }
+        if self.remaining.is_some() && !self.voted {
+            if typed.chars().any(|c| c.eq_ignore_ascii_case(&'t')) {
+                self.same_team = !self.same_team;
+            }
+            if input::keyboard::is_key_pressed(ctx, Key::Enter) {
+                self.vote::<ID>(connection, true);
+            }
+        }
+        None
+    }
+
+    pub fn draw(&self, ctx: &mut GameContext) {
+        let params = DrawParams::color(TextColor::White.into());
+        let mut lines = vec!["Battle over!".to_owned()];
+        if let Some(change) = self.rating {
+            lines.push(format!(
+                "Rating {:.0} ({:+.0})",
+                change.rating, change.change
+            ));
+        }
+        match self.remaining {
+            Some(remaining) => {
+                lines.push(format!("Rematch? {} seconds left", remaining.ceil()));
+                if !self.voted {
+                    lines.push("Enter: accept, Escape: leave".to_owned());
+                    lines.push(format!(
+                        "T: {}",
+                        match self.same_team {
+                            true => "keep team",
+                            false => "new team",
+                        }
+                    ));
+                }
+                let accepted = self
+                    .votes
+                    .iter()
+                    .filter(|vote| **vote == Some(true))
+                    .count();
+                lines.push(format!(
+                    "{} of {} players accepted",
+                    accepted,
+                    self.votes.len()
+                ));
+            }
+            None if self.ranked() => lines.push("Escape: back to lobby".to_owned()),
+            None => lines.push("Escape: leave".to_owned()),
+        }
+        for (index, line) in lines.iter().enumerate() {
+            draw_text_left(
+                &mut ctx.engine,
+                &1,
+                line,
+                5.0,
+                5.0 + index as f32 * 15.0,
+                params,
+            );
+        }
+    }
+
+    /// Ranked battles change ratings and are not offered rematches,
+    /// their players go back to the lobby instead.
+    fn ranked(&self) -> bool {
+        self.remaining.is_none() && self.rating.is_some()
+    }
+
+    fn vote<ID: Serialize>(&mut self, connection: &mut BattleConnection, accept: bool) {
+        self.voted = true;
+        connection.send(&NetClientMessage::<ID>::Rematch(RematchVote {
+            accept,
+            same_team: self.same_team,
+        }));
+    }
+}
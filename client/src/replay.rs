@@ -0,0 +1,114 @@
+use std::collections::VecDeque;
+
+use gui::pokedex::engine::{
+    input::{self, keyboard::Key},
+    log::warn,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use common::{
+    battle::message::ServerMessage,
+    pokedex::pokemon::{owned::SavedPokemon, party::Party},
+    replay::{Replay, ReplayMessage},
+};
+
+use crate::GameContext;
+
+const MIN_SPEED: f32 = 0.25;
+const MAX_SPEED: f32 = 8.0;
+
+/// Plays back a recorded battle from the point of view of one of its players.
+pub struct ReplayViewer<ID> {
+    messages: VecDeque<ReplayMessage<ID>>,
+    /// Seconds into the recording.
+    time: f32,
+    pub speed: f32,
+    pub paused: bool,
+}
+
+impl<ID: Serialize + DeserializeOwned> ReplayViewer<ID> {
+    /// Load a replay file, returning the party of the player it is viewed as.
+    pub fn load(path: &str) -> Option<(Self, Party<SavedPokemon>)> {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            match std::fs::read(path) {
+                Ok(bytes) => match Replay::from_bytes(&bytes, unsafe { crate::BUNDLES.dex }) {
+                    Ok(replay) => return Self::new(replay),
+                    Err(err) => warn!("Could not read replay at {} with error {}", path, err),
+                },
+                Err(err) => warn!("Could not open replay at {} with error {}", path, err),
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        warn!("Cannot load replay at {} on this platform", path);
+        None
+    }
+
+    fn new(replay: Replay<ID>) -> Option<(Self, Party<SavedPokemon>)> {
+        // AI players do not have their messages recorded
+        match replay
+            .players
+            .into_iter()
+            .find(|player| !player.messages.is_empty())
+        {
+            Some(player) => Some((
+                Self {
+                    messages: player.messages.into(),
+                    time: 0.0,
+                    speed: 1.0,
+                    paused: false,
+                },
+                player.party,
+            )),
+            None => {
+                warn!("Replay does not have any recorded messages");
+                None
+            }
+        }
+    }
+}
+
+impl<ID> ReplayViewer<ID> {
+    /// Handle playback controls and return the messages that are due.
+    pub fn update(&mut self, ctx: &GameContext, delta: f32) -> Vec<ServerMessage<ID>> {
+        if input::keyboard::is_key_pressed(ctx, Key::Space) {
+            self.paused = !self.paused;
+        }
+        if input::keyboard::is_key_pressed(ctx, Key::Up) {
+            self.speed = (self.speed * 2.0).min(MAX_SPEED);
+        }
+        if input::keyboard::is_key_pressed(ctx, Key::Down) {
+            self.speed = (self.speed / 2.0).max(MIN_SPEED);
+        }
+
+        let mut due = Vec::new();
+
+        if self.paused {
+            // Step through the recording one message at a time
+            if input::keyboard::is_key_pressed(ctx, Key::Right) {
+                if let Some(message) = self.messages.pop_front() {
+                    self.time = message.time;
+                    due.push(message.message);
+                }
+            }
+        } else {
+            self.time += delta * self.speed;
+            while self
+                .messages
+                .front()
+                .map(|message| message.time <= self.time)
+                .unwrap_or_default()
+            {
+                if let Some(message) = self.messages.pop_front() {
+                    due.push(message.message);
+                }
+            }
+        }
+
+        due
+    }
+
+    pub fn finished(&self) -> bool {
+        self.messages.is_empty()
+    }
+}
@@ -1,5 +1,4 @@
 use gui::pokedex::engine::log::{debug, error, info, warn};
-use naia_client_socket::{PacketReceiver, Socket};
 use serde::{de::DeserializeOwned, Serialize};
 use std::{fmt::Debug, hash::Hash};
 
@@ -9,116 +8,291 @@ use common::{
         message::ServerMessage,
     },
     deserialize,
-    pokedex::{item::Item, moves::Move, pokemon::Pokemon},
-    serialize, ConnectMessage, NetClientMessage, NetServerMessage, Player, VERSION,
+    pokedex::{
+        item::Item,
+        moves::Move,
+        pokemon::{owned::SavedPokemon, party::Party, Pokemon},
+    },
+    ranking::{RankingMessage, RankingRequest},
+    reliable::Session,
+    serialize,
+    socket::{Endpoint, ReliableSocket},
+    BattleId, BattleSetup, Clock, ConnectMessage, NetClientMessage, NetServerMessage, Player,
+    RematchMessage, SessionToken, PROTOCOL_VERSION,
 };
 
 use gui::BattlePlayerGui;
 
-use crate::{ConnectState, GameContext, GuiPlayer, net::{Endpoint, PacketSender}};
+use crate::{chat::Chat, lobby::Lobby, ConnectState, GameContext, GuiPlayer};
+
+/// Seconds between pings sent to the server.
+const PING_INTERVAL: f32 = 1.0;
+/// Seconds without hearing from the server before it is considered unresponsive.
+const NOT_RESPONDING: f32 = 5.0;
+/// Seconds without hearing from the server before trying to reconnect to a battle.
+const RECONNECT: f32 = 10.0;
 
 pub struct BattleConnection {
-    socket: Socket,
+    socket: ReliableSocket,
+    address: Endpoint,
+    session: Session,
+    /// Token to take our place in a battle back with after reconnecting.
+    token: Option<SessionToken>,
     // endpoint: Endpoint,
-    sender: PacketSender,
-    receiver: PacketReceiver,
     // receiver: Receiver<NetServerMessage<ID>>,
     name: Option<String>,
-    accumulator: f32,
+    /// Password of the account with the player's name.
+    password: Option<String>,
+    team: Option<Party<SavedPokemon>>,
+    /// Seconds since the last ping was sent.
+    ping: f32,
+    /// Seconds since the server was last heard from.
+    silence: f32,
+    /// Time left to act, counted down while it is running.
+    clock: Option<Clock>,
+    /// Format and teams of the current battle.
+    setup: Option<BattleSetup>,
+    pub chat: Chat,
 }
 
 impl BattleConnection {
-    pub fn connect(address: Endpoint, name: Option<String>) -> Option<Self> {
+    pub fn connect<ID: Serialize>(
+        address: Endpoint,
+        session: Session,
+        name: Option<String>,
+        password: Option<String>,
+        team: Option<Party<SavedPokemon>>,
+    ) -> Option<Self> {
+        let mut connection = Self::new(address, session, name, password, team);
+
+        // Delivery is reliable, so the request only needs to be sent once
+        connection.send(&NetClientMessage::<ID>::RequestJoin(
+            PROTOCOL_VERSION,
+            unsafe { crate::BUNDLES },
+        ));
+
+        Some(connection)
+    }
 
-        let mut socket = Socket::new(Default::default());
+    /// Connect to watch a battle, or any battle if none is given.
+    pub fn spectate<ID: Serialize>(
+        address: Endpoint,
+        session: Session,
+        battle: Option<BattleId>,
+    ) -> Option<Self> {
+        let mut connection = Self::new(address, session, None, None, None);
 
-        socket.connect(address);
+        connection.send(&NetClientMessage::<ID>::Spectate(battle));
 
-        let sender = PacketSender::from(socket.get_packet_sender());
-        let receiver = socket.get_packet_receiver();
+        Some(connection)
+    }
+
+    fn new(
+        address: Endpoint,
+        session: Session,
+        name: Option<String>,
+        password: Option<String>,
+        team: Option<Party<SavedPokemon>>,
+    ) -> Self {
+        let socket = ReliableSocket::connect(address, session);
 
         // let mut socket = QuadSocket::connect(address).ok()?;
 
-        Some(Self {
+        Self {
             socket,
-            sender,
-            receiver,
+            address,
+            session,
+            token: None,
             // endpoint: address,
             name,
-            accumulator: 9.9,
-        })
+            password,
+            team,
+            ping: 0.0,
+            silence: 0.0,
+            clock: None,
+            setup: None,
+            chat: Default::default(),
+        }
     }
 
     pub fn end<ID: Serialize>(&mut self) {
-        self.sender
-            .send(serialize(&NetClientMessage::<ID>::Leave).unwrap());
+        self.send(&NetClientMessage::<ID>::Leave);
         // self.controller.remove(self.endpoint.resource_id());
     }
 
+    /// Ping the server and resend anything it has not acknowledged yet.
+    pub fn update<ID: Serialize>(&mut self, delta: f32) {
+        self.ping += delta;
+        self.silence += delta;
+        if self.ping >= PING_INTERVAL {
+            self.ping -= PING_INTERVAL;
+            self.send(&NetClientMessage::<ID>::Ping);
+        }
+        if let Some(token) = self.token {
+            if self.silence >= RECONNECT {
+                warn!("Server is not responding, reconnecting to battle.");
+                self.session = self.session.wrapping_add(1);
+                self.socket = ReliableSocket::connect(self.address, self.session);
+                self.silence = 0.0;
+                self.send(&NetClientMessage::<ID>::Rejoin(token));
+            }
+        }
+        if let Some(clock) = self.clock.as_mut().filter(|clock| clock.running) {
+            for time in [clock.turn.as_mut(), clock.total.as_mut()]
+                .into_iter()
+                .flatten()
+            {
+                *time = (*time - delta).max(0.0);
+            }
+        }
+        self.socket.update(delta);
+    }
+
+    /// Time left to act, if the battle is timed.
+    pub fn clock(&self) -> Option<&Clock> {
+        self.clock.as_ref()
+    }
+
+    /// Let the player use the chat, returning whether it has the keyboard.
+    pub fn update_chat<ID: Serialize>(&mut self, ctx: &GameContext) -> bool {
+        if let Some(text) = self.chat.update(ctx) {
+            self.send(&NetClientMessage::<ID>::Chat(text));
+        }
+        self.chat.open
+    }
+
+    /// Format and players of the current battle, once it has started.
+    pub fn setup(&self) -> Option<&BattleSetup> {
+        self.setup.as_ref()
+    }
+
+    /// If the server has been heard from recently.
+    pub fn responding(&self) -> bool {
+        self.silence < NOT_RESPONDING
+    }
+
     pub(crate) fn wait_confirm<ID: Serialize + DeserializeOwned>(
         &mut self,
         ctx: &mut GameContext,
         player: &mut GuiPlayer,
-        delta: f32,
     ) -> Option<ConnectState> {
-        self.accumulator += delta;
-        if self.accumulator >= 10.0 {
-            self.sender.send(
-                serialize(&NetClientMessage::<ID>::RequestJoin(VERSION.to_owned())).unwrap(),
-            );
-            self.accumulator -= 10.0;
-        }
         if let Some(message) = self.recv::<ID>() {
             match message {
                 NetServerMessage::Validate(message) => {
-                    return Some(match message {
+                    return match message {
                         ConnectMessage::CanJoin(party) => {
                             info!("Server accepted connection!");
-
-                            let name = self.name.take().unwrap_or_else(|| {
-                                use rand::{distributions::Alphanumeric, Rng};
-                                std::iter::repeat(())
-                                    .map(|()| ctx.random.sample(Alphanumeric))
-                                    .map(char::from)
-                                    .take(7)
-                                    .collect()
-                            });
-
-                            let m = NetClientMessage::<ID>::Join(Player { name });
-
-                            self.send(&m);
-
-                            if let NetClientMessage::Join(p) = m {
-                                let pokedex = unsafe { crate::POKEDEX.as_ref().unwrap() };
-                                let movedex = unsafe { crate::MOVEDEX.as_ref().unwrap() };
-                                let itemdex = unsafe { crate::ITEMDEX.as_ref().unwrap() };
-                                player.party = party
-                                    .into_iter()
-                                    .map(|o| {
-                                        o.init(&mut ctx.random, pokedex, movedex, itemdex)
-                                            .unwrap_or_else(|| {
-                                                panic!("Could not initialize generated pokemon!")
-                                            })
-                                    })
-                                    .collect();
+                            match self.team.clone() {
+                                Some(team) => {
+                                    self.send(&NetClientMessage::<ID>::Team(team));
+                                    None
+                                }
+                                None => match party {
+                                    Some(party) => Some(self.join::<ID>(ctx, player, party)),
+                                    None => {
+                                        warn!("Server requires a team but none was given!");
+                                        Some(ConnectState::TeamRejected(Vec::new(), 5.0))
+                                    }
+                                },
                             }
-
-                            ConnectState::ConnectedWait
+                        }
+                        ConnectMessage::TeamAccepted => match self.team.take() {
+                            Some(team) => {
+                                info!("Server accepted team!");
+                                Some(self.join::<ID>(ctx, player, team))
+                            }
+                            None => None,
+                        },
+                        ConnectMessage::TeamRejected => {
+                            warn!("Server does not accept submitted teams!");
+                            Some(ConnectState::TeamRejected(Vec::new(), 5.0))
+                        }
+                        ConnectMessage::Spectating(id) => {
+                            info!("Spectating battle {}", id);
+                            Some(ConnectState::Spectating)
+                        }
+                        ConnectMessage::NoBattle => {
+                            warn!("There is no battle to spectate!");
+                            Some(ConnectState::Closed)
+                        }
+                        ConnectMessage::WrongVersion(range) => {
+                            warn!(
+                                "Server supports protocol {} to {} but client uses {}, server is version {}",
+                                range.min, range.max, PROTOCOL_VERSION, range.version
+                            );
+                            Some(ConnectState::WrongVersion(Some(range), 10.0))
+                        }
+                        ConnectMessage::WrongBundles => {
+                            warn!("Server is running with different dex or battle data!");
+                            Some(ConnectState::WrongBundles(5.0))
+                        }
+                        ConnectMessage::IllegalTeam(problems) => {
+                            for problem in problems.iter() {
+                                warn!("Illegal team: {}", problem);
+                            }
+                            Some(ConnectState::TeamRejected(problems, 10.0))
                         }
                         other => {
                             warn!("Cannot join server with error \"{:?}\"", other);
-                            ConnectState::Closed
+                            Some(ConnectState::Closed)
                         }
-                    });
+                    };
                 }
                 NetServerMessage::Game(..) => {
                     error!("Received game message when not in game!")
                 }
+                NetServerMessage::Pong
+                | NetServerMessage::Clock(..)
+                | NetServerMessage::TimedOut
+                | NetServerMessage::Lobby(..)
+                | NetServerMessage::Chat(..)
+                | NetServerMessage::ChatRejected(..)
+                | NetServerMessage::Rematch(..)
+                | NetServerMessage::Stats(..)
+                | NetServerMessage::Ranking(..)
+                | NetServerMessage::Tournament(..)
+                | NetServerMessage::Setup(..) => (),
             }
         }
         None
     }
 
+    /// Go back to the lobby after a battle the server does not offer a rematch for.
+    pub(crate) fn lobby(&self) -> Lobby {
+        Lobby::new(self.name.clone().unwrap_or_default())
+    }
+
+    fn join<ID: Serialize>(
+        &mut self,
+        ctx: &mut GameContext,
+        player: &mut GuiPlayer,
+        party: Party<SavedPokemon>,
+    ) -> ConnectState {
+        let name = self.name.clone().unwrap_or_else(|| {
+            use rand::{distributions::Alphanumeric, Rng};
+            std::iter::repeat(())
+                .map(|()| ctx.random.sample(Alphanumeric))
+                .map(char::from)
+                .take(7)
+                .collect()
+        });
+
+        // Kept for going back to the lobby between tournament matches
+        self.name = Some(name.clone());
+        self.send(&NetClientMessage::<ID>::Join(Player {
+            name: name.clone(),
+            password: self.password.take(),
+        }));
+        self.send(&NetClientMessage::<ID>::Stats(name.clone()));
+        self.send(&NetClientMessage::<ID>::Ranking(RankingRequest::History(
+            name.clone(),
+        )));
+
+        set_party(ctx, player, party);
+
+        ConnectState::Lobby(Lobby::new(name))
+    }
+
     pub(crate) fn gui_receive<'d, ID: Default + Eq + Hash + Debug + Clone + DeserializeOwned>(
         &mut self,
         gui: &mut BattlePlayerGui<ID, &'d Pokemon, &'d Move, &'d Item>,
@@ -134,37 +308,83 @@ impl BattleConnection {
 
                     match &message {
                         ServerMessage::Begin(..) => {
-                            *state = ConnectState::ConnectedPlay;
-                            let npc = "rival".parse().unwrap();
-                            for r in gui.remotes.values_mut() {
-                                r.npc_group = Some(npc);
+                            if !matches!(state, ConnectState::Spectating) {
+                                *state = ConnectState::ConnectedPlay;
                             }
-                            gui.start(true);
-                        }
-                        ServerMessage::PlayerEnd(..) | ServerMessage::GameEnd(..) => {
-                            *state = ConnectState::Closed;
                         }
+                        ServerMessage::PlayerEnd(..) | ServerMessage::GameEnd(..) => match state {
+                            ConnectState::Spectating => *state = ConnectState::Closed,
+                            ConnectState::Rematch(..) => (),
+                            _ => *state = ConnectState::Rematch(Default::default()),
+                        },
                         _ => (),
                     }
 
-                    endpoint.send(message); // give gui the message
-                    let pokedex = unsafe { crate::POKEDEX.as_ref().unwrap() };
-                    let movedex = unsafe { crate::MOVEDEX.as_ref().unwrap() };
-                    let itemdex = unsafe { crate::ITEMDEX.as_ref().unwrap() };
-                    gui.process(
-                        &mut ctx.random,
-                        &ctx.dex,
-                        &ctx.btl,
-                        pokedex,
-                        movedex,
-                        itemdex,
-                        &mut player.party,
-                    ); // process messages
+                    gui_process(gui, player, endpoint, ctx, message);
+                }
+                NetServerMessage::Validate(ConnectMessage::Rejoined) => {
+                    info!("Rejoined battle, catching up.");
+                    *state = ConnectState::ConnectedWait;
+                }
+                NetServerMessage::Validate(ConnectMessage::UnknownSession) => {
+                    warn!("Could not rejoin battle as it has ended.");
+                    *state = ConnectState::Closed;
+                }
+                NetServerMessage::Validate(ConnectMessage::LoginFailed(err)) => {
+                    warn!("Could not log in: {}", err);
+                    *state = ConnectState::LoginFailed(err, 5.0);
                 }
                 NetServerMessage::Validate(message) => {
                     warn!("Received client validation message \"{:?}\"", message);
-                    *state = ConnectState::WrongVersion(5.0);
+                    *state = ConnectState::WrongVersion(None, 5.0);
                 }
+                NetServerMessage::Lobby(message) => {
+                    if let ConnectState::Lobby(lobby) = state {
+                        if let Some(next) = lobby.receive(message) {
+                            *state = next;
+                        }
+                    }
+                }
+                NetServerMessage::Ranking(message) => match state {
+                    ConnectState::Lobby(lobby) => lobby.ranking(message),
+                    ConnectState::Rematch(rematch) => {
+                        if let RankingMessage::Updated(change) = message {
+                            rematch.rating = Some(change);
+                        }
+                    }
+                    _ => (),
+                },
+                NetServerMessage::Stats(name, stats) => {
+                    if let ConnectState::Lobby(lobby) = state {
+                        lobby.stats(name, stats);
+                    }
+                }
+                NetServerMessage::Rematch(message) => {
+                    if let RematchMessage::Starting(Some(party)) = &message {
+                        set_party(ctx, player, party.clone());
+                    }
+                    if let ConnectState::Rematch(rematch) = state {
+                        if let Some(next) = rematch.receive(message) {
+                            *state = next;
+                        }
+                    }
+                }
+                NetServerMessage::Tournament(bracket) => match state {
+                    ConnectState::Lobby(lobby) => lobby.tournament(bracket),
+                    // Wait for the next match in the lobby
+                    ConnectState::Rematch(..) => {
+                        let mut lobby = self.lobby();
+                        lobby.tournament(bracket);
+                        *state = ConnectState::Lobby(lobby);
+                    }
+                    _ => (),
+                },
+                NetServerMessage::Pong
+                | NetServerMessage::Clock(..)
+                | NetServerMessage::TimedOut
+                | NetServerMessage::Chat(..)
+                | NetServerMessage::ChatRejected(..)
+                | NetServerMessage::Setup(..) => (),
             }
         }
     }
@@ -177,21 +397,97 @@ impl BattleConnection {
 
     pub fn send<ID: Serialize>(&mut self, message: &NetClientMessage<ID>) {
         match serialize(message) {
-            Ok(bytes) => self.sender.send(bytes),
+            Ok(bytes) => self.socket.send(bytes),
             Err(err) => todo!("{}", err),
         }
     }
 
     pub fn recv<ID: DeserializeOwned>(&mut self) -> Option<NetServerMessage<ID>> {
-        match self.receiver.receive() {
-            Ok(Some(packet)) => match deserialize::<NetServerMessage<ID>>(packet.payload()) {
-                Ok(message) => Some(message),
+        loop {
+            let bytes = self.socket.receive()?;
+            self.silence = 0.0;
+            match deserialize::<NetServerMessage<ID>>(&bytes) {
+                Ok(NetServerMessage::Pong) => continue,
+                Ok(NetServerMessage::Validate(ConnectMessage::Session(token))) => {
+                    self.token = Some(token);
+                    continue;
+                }
+                Ok(NetServerMessage::Clock(clock)) => {
+                    self.clock = Some(clock);
+                    continue;
+                }
+                Ok(NetServerMessage::Setup(setup)) => {
+                    self.setup = Some(setup);
+                    continue;
+                }
+                Ok(NetServerMessage::TimedOut) => {
+                    warn!("Ran out of time, the server has acted for us.");
+                    continue;
+                }
+                Ok(NetServerMessage::Chat(message)) => {
+                    self.chat.receive(message);
+                    continue;
+                }
+                Ok(NetServerMessage::ChatRejected(err)) => {
+                    self.chat.rejected(err);
+                    continue;
+                }
+                Ok(message) => return Some(message),
                 Err(err) => {
                     warn!("Could not receive server message with error {}", err);
-                    None
+                    return None;
                 }
-            },
-            _ => None,
+            }
         }
     }
 }
+
+/// Initialize a saved party for the gui.
+pub(crate) fn set_party(ctx: &mut GameContext, player: &mut GuiPlayer, party: Party<SavedPokemon>) {
+    let pokedex = unsafe { crate::POKEDEX.as_ref().unwrap() };
+    let movedex = unsafe { crate::MOVEDEX.as_ref().unwrap() };
+    let itemdex = unsafe { crate::ITEMDEX.as_ref().unwrap() };
+    player.party = party
+        .into_iter()
+        .map(|o| {
+            o.init(&mut ctx.random, pokedex, movedex, itemdex)
+                .unwrap_or_else(|| panic!("Could not initialize party pokemon!"))
+        })
+        .collect();
+}
+
+/// Give the gui a game message and let it process it.
+pub(crate) fn gui_process<'d, ID: Default + Eq + Hash + Debug + Clone>(
+    gui: &mut BattlePlayerGui<ID, &'d Pokemon, &'d Move, &'d Item>,
+    player: &mut GuiPlayer<'d>,
+    endpoint: &mut MpscEndpoint<ID>,
+    ctx: &mut GameContext,
+    message: ServerMessage<ID>,
+) {
+    if let ServerMessage::Begin(..) = &message {
+        let npc = "rival".parse().unwrap();
+        for r in gui.remotes.values_mut() {
+            r.npc_group = Some(npc);
+        }
+        gui.start(true);
+    }
+
+    endpoint.send(message); // give gui the message
+    let pokedex = unsafe { crate::POKEDEX.as_ref().unwrap() };
+    let movedex = unsafe { crate::MOVEDEX.as_ref().unwrap() };
+    let itemdex = unsafe { crate::ITEMDEX.as_ref().unwrap() };
+    gui.process(
+        &mut ctx.random,
+        &ctx.dex,
+        &ctx.btl,
+        pokedex,
+        movedex,
+        itemdex,
+        &mut player.party,
+    ); // process messages
+}
+
+/// Throw away anything the gui sends, as spectators and replays cannot act.
+pub(crate) fn gui_discard<ID>(endpoint: &mut MpscEndpoint<ID>) {
+    while BattleEndpoint::receive(endpoint).is_ok() {}
+}
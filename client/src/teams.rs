@@ -0,0 +1,92 @@
+use core::hash::Hash;
+
+use common::{
+    lobby::BattleFormat,
+    pokedex::{item::Item, moves::Move, pokemon::Pokemon},
+    BattleSetup,
+};
+
+use gui::{
+    pokedex::pokemon::{owned::OwnedPokemon, party::Party},
+    BattlePlayerGui,
+};
+
+use crate::{
+    engine::{
+        graphics::{draw_text_left, DrawParams},
+        text::TextColor,
+    },
+    GameContext,
+};
+
+/// Draw each player and the pokemon in each of their active slots from `y` down.
+/// Singles are left to the battle gui, which already shows both players.
+pub fn draw<'d, ID: Eq + Hash + From<u8>>(
+    ctx: &mut GameContext,
+    setup: &BattleSetup,
+    gui: &BattlePlayerGui<ID, &'d Pokemon, &'d Move, &'d Item>,
+    party: &Party<OwnedPokemon<&'d Pokemon, &'d Move, &'d Item>>,
+    y: f32,
+) {
+    if setup.format == BattleFormat::Singles {
+        return;
+    }
+    let params = DrawParams::color(TextColor::White.into());
+
+    let mut lines = vec![setup.format.to_string()];
+
+    for (index, name) in setup.players.iter().enumerate() {
+        let name = name
+            .clone()
+            .unwrap_or_else(|| format!("Player {}", index + 1));
+        let slots = (0..setup.format.active())
+            .map(|slot| {
+                let active = match setup.you == Some(index as u8) {
+                    true => local_active(gui, party, slot),
+                    false => remote_active(gui, index as u8, slot),
+                };
+                format!("[{}]", active.unwrap_or("-"))
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        let you = match setup.you == Some(index as u8) {
+            true => " (you)",
+            false => "",
+        };
+        lines.push(format!("{}{}: {}", name, you, slots));
+    }
+
+    for (index, line) in lines.iter().enumerate() {
+        draw_text_left(
+            &mut ctx.engine,
+            &1,
+            line,
+            5.0,
+            y + index as f32 * 15.0,
+            params,
+        );
+    }
+}
+
+/// Name of the pokemon the player has out in a slot.
+fn local_active<'d, ID>(
+    gui: &BattlePlayerGui<ID, &'d Pokemon, &'d Move, &'d Item>,
+    party: &Party<OwnedPokemon<&'d Pokemon, &'d Move, &'d Item>>,
+    slot: usize,
+) -> Option<&'d str> {
+    let index = gui.local.as_ref()?.player.active.get(slot).copied()??;
+    let pokemon: &'d Pokemon = party.get(index)?.pokemon;
+    Some(pokemon.name.as_str())
+}
+
+/// Name of the pokemon another player has out in a slot, once it has been revealed.
+fn remote_active<'d, ID: Eq + Hash + From<u8>>(
+    gui: &BattlePlayerGui<ID, &'d Pokemon, &'d Move, &'d Item>,
+    player: u8,
+    slot: usize,
+) -> Option<&'d str> {
+    let remote = gui.remotes.get(&ID::from(player))?;
+    let index = remote.player.active.get(slot).copied()??;
+    let pokemon: &'d Pokemon = remote.player.pokemon.get(index)?.as_ref()?.pokemon;
+    Some(pokemon.name.as_str())
+}
@@ -0,0 +1,253 @@
+use std::{
+    collections::BTreeMap,
+    fs::{read_to_string, rename, write},
+    io::ErrorKind,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use common::{
+    account::{LoginError, MatchRecord, MatchResult, PlayerStats, MAX_NAME_LENGTH},
+    ranking::{expected, LeaderboardEntry, Rating, RatingChange},
+};
+
+use crate::battle::SeatResult;
+
+/// Number of battles kept in each account's history.
+const HISTORY: usize = 20;
+/// Number of rating changes kept in each account's rating history.
+const RATING_HISTORY: usize = 50;
+/// Most an Elo rating can change by in one battle.
+const K_FACTOR: f64 = 32.0;
+/// Number of accounts on the leaderboard.
+const LEADERBOARD: usize = 20;
+
+/// Accounts are identified by the name they were registered with,
+/// which guests cannot log in with.
+pub type AccountId = String;
+
+/// Player accounts, saved to a file whenever they change.
+pub struct Accounts {
+    path: PathBuf,
+    accounts: BTreeMap<AccountId, Account>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Account {
+    /// Argon2 hash of the account's password.
+    hash: String,
+    stats: PlayerStats,
+    #[serde(default)]
+    rating: Rating,
+}
+
+impl Accounts {
+    /// Load the accounts file, starting with no accounts if it does not exist.
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        let accounts = match read_to_string(&path) {
+            Ok(data) => toml::from_str(&data).map_err(|err| {
+                format!(
+                    "Could not parse accounts file at {:?} with error {}",
+                    path, err
+                )
+            })?,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                info!("No accounts file found at {:?}, starting a new one.", path);
+                Default::default()
+            }
+            Err(err) => {
+                return Err(format!(
+                    "Could not read accounts file at {:?} with error {}",
+                    path, err
+                ))
+            }
+        };
+        Ok(Self { path, accounts })
+    }
+
+    /// Log in to the account with the name, creating it if it does not exist and a password is given.
+    /// Returns the account the player logged in to, or none for guests.
+    pub fn login(
+        &mut self,
+        name: &str,
+        password: Option<&str>,
+    ) -> Result<Option<AccountId>, LoginError> {
+        match (self.accounts.get(name), password) {
+            (Some(account), Some(password)) => {
+                let verified = PasswordHash::new(&account.hash)
+                    .map(|hash| {
+                        Argon2::default()
+                            .verify_password(password.as_bytes(), &hash)
+                            .is_ok()
+                    })
+                    .unwrap_or_default();
+                match verified {
+                    true => Ok(Some(name.to_owned())),
+                    false => Err(LoginError::WrongPassword),
+                }
+            }
+            (Some(..), None) => Err(LoginError::NameTaken),
+            (None, Some(password)) => {
+                if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+                    return Err(LoginError::InvalidName);
+                }
+                let salt = SaltString::generate(&mut OsRng);
+                let hash = match Argon2::default().hash_password(password.as_bytes(), &salt) {
+                    Ok(hash) => hash.to_string(),
+                    Err(err) => {
+                        error!("Could not hash password with error {}", err);
+                        return Ok(None);
+                    }
+                };
+                info!("Created account {}", name);
+                self.accounts.insert(
+                    name.to_owned(),
+                    Account {
+                        hash,
+                        stats: Default::default(),
+                        rating: Default::default(),
+                    },
+                );
+                self.save();
+                Ok(Some(name.to_owned()))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+
+    pub fn stats(&self, name: &str) -> Option<PlayerStats> {
+        self.accounts.get(name).map(|account| account.stats.clone())
+    }
+
+    pub fn rating(&self, name: &str) -> Option<&Rating> {
+        self.accounts.get(name).map(|account| &account.rating)
+    }
+
+    /// Highest rated accounts that have played a ranked battle, best first.
+    pub fn leaderboard(&self) -> Vec<LeaderboardEntry> {
+        let mut entries = self
+            .accounts
+            .iter()
+            .filter(|(.., account)| !account.rating.history.is_empty())
+            .map(|(name, account)| LeaderboardEntry {
+                name: name.clone(),
+                rating: account.rating.rating,
+                battles: account.rating.history.len() as _,
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| b.rating.total_cmp(&a.rating));
+        entries.truncate(LEADERBOARD);
+        entries
+    }
+
+    /// Update the Elo ratings of the two players of a ranked battle, returning their changes.
+    pub fn rate(&mut self, results: &[SeatResult]) -> Vec<(AccountId, RatingChange)> {
+        let (a, b) = match results {
+            [a, b] => (a, b),
+            _ => return Vec::new(),
+        };
+        // Guests have no rating to change
+        let (a, b) = match (&a.account, &b.account) {
+            (Some(id_a), Some(id_b)) => ((id_a, a.result), (id_b, b.result)),
+            _ => return Vec::new(),
+        };
+        let ratings = match (self.rating(a.0), self.rating(b.0)) {
+            (Some(ra), Some(rb)) => [ra.rating, rb.rating],
+            _ => return Vec::new(),
+        };
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or_default();
+        let mut changes = Vec::with_capacity(2);
+        for (index, &(id, result)) in [a, b].iter().enumerate() {
+            let score = match result {
+                MatchResult::Win => 1.0,
+                MatchResult::Loss => 0.0,
+                MatchResult::Draw => 0.5,
+            };
+            let change = K_FACTOR * (score - expected(ratings[index], ratings[1 - index]));
+            if let Some(account) = self.accounts.get_mut(id) {
+                let rating = &mut account.rating;
+                rating.rating += change;
+                let change = RatingChange {
+                    time,
+                    rating: rating.rating,
+                    change,
+                };
+                rating.history.push(change);
+                if rating.history.len() > RATING_HISTORY {
+                    rating.history.remove(0);
+                }
+                changes.push((id.clone(), change));
+            }
+        }
+        self.save();
+        changes
+    }
+
+    /// Add a finished battle to the records of the players that logged in to accounts.
+    /// Guests are only listed as opponents.
+    pub fn record(&mut self, results: &[SeatResult]) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or_default();
+        let mut changed = false;
+        for (index, seat) in results.iter().enumerate() {
+            let account = match seat.account.as_ref() {
+                Some(id) => match self.accounts.get_mut(id) {
+                    Some(account) => account,
+                    None => continue,
+                },
+                None => continue,
+            };
+            let stats = &mut account.stats;
+            match seat.result {
+                MatchResult::Win => stats.wins += 1,
+                MatchResult::Loss => stats.losses += 1,
+                MatchResult::Draw => stats.draws += 1,
+            }
+            stats.history.push(MatchRecord {
+                time,
+                result: seat.result,
+                opponents: results
+                    .iter()
+                    .enumerate()
+                    .filter(|(opponent, ..)| *opponent != index)
+                    .map(|(.., opponent)| opponent.name.clone())
+                    .collect(),
+            });
+            if stats.history.len() > HISTORY {
+                stats.history.remove(0);
+            }
+            changed = true;
+        }
+        if changed {
+            self.save();
+        }
+    }
+
+    /// Write the accounts to a temporary file and move it over the old one,
+    /// so the file is never left half written.
+    fn save(&self) {
+        let data = match toml::to_string(&self.accounts) {
+            Ok(data) => data,
+            Err(err) => return error!("Could not serialize accounts with error {}", err),
+        };
+        let temporary = self.path.with_extension("tmp");
+        if let Err(err) = write(&temporary, data).and_then(|()| rename(&temporary, &self.path)) {
+            error!(
+                "Could not save accounts to {:?} with error {}",
+                self.path, err
+            );
+        }
+    }
+}
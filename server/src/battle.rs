@@ -0,0 +1,544 @@
+use std::time::{Duration, Instant};
+
+use rand::prelude::StdRng;
+
+use common::{
+    account::MatchResult,
+    battle::{
+        endpoint::BattleEndpoint,
+        message::{ClientMessage, ServerMessage},
+        prelude::{Battle, DefaultMoveEngine},
+    },
+    lobby::BattleFormat,
+    pokedex::{
+        item::Item,
+        moves::Move,
+        pokemon::{owned::SavedPokemon, party::Party, Pokemon},
+        BasicDex,
+    },
+    replay::{Replay, ReplayMessage},
+    BattleId, BattleSetup, ConnectMessage, Id, NetServerMessage, SessionToken,
+};
+
+use crossbeam_channel::{Receiver, Sender};
+
+use crate::{
+    accounts::AccountId,
+    clock::{self, Expiry, SeatClock},
+    configuration::TimeoutAction,
+    net::*,
+    player::BattleServerPlayer,
+    rematch::Rematch,
+    serialize,
+    spectator::SpectatorView,
+};
+
+/// How long a disconnected player has to rejoin before they are removed from the battle.
+const REJOIN_TIME: Duration = Duration::from_secs(60);
+
+pub type ServerBattle<'d> = Battle<Id, &'d Pokemon, &'d Move, &'d Item>;
+
+/// A running battle and the seats of the players connected to it.
+pub struct BattleInstance<'d> {
+    battle: ServerBattle<'d>,
+    format: BattleFormat,
+    /// Random number generator of the battle, seeded when the battle started.
+    random: StdRng,
+    seats: Vec<Seat>,
+    /// Messages sent to the AI players, to learn which pokemon have been revealed to them.
+    observed: Vec<Receiver<ServerMessage<Id>>>,
+    spectators: Vec<Endpoint>,
+    spectator: SpectatorView,
+    /// Public messages sent to spectators, so they can be resent to new spectators.
+    public: Vec<Vec<u8>>,
+    /// Recording of the battle, if replays are enabled.
+    replay: Option<Replay<Id>>,
+    started: Instant,
+    /// Winner of the battle once it has ended, if there was one.
+    winner: Option<Option<Id>>,
+}
+
+/// A human player with what they need to sit in a battle.
+pub struct SeatPlayer {
+    pub endpoint: Endpoint,
+    pub token: SessionToken,
+    /// Name the player is shown with in chat.
+    pub name: String,
+    /// Account the player logged in to, none for guests.
+    pub account: Option<AccountId>,
+    pub party: Party<SavedPokemon>,
+}
+
+/// How a battle ended for one of its human players.
+pub struct SeatResult {
+    pub name: String,
+    pub account: Option<AccountId>,
+    pub result: MatchResult,
+}
+
+/// A human player's place in a battle, which can be taken back after reconnecting.
+pub struct Seat {
+    /// Index of the seat's player in the battle.
+    player: usize,
+    /// Name the player is shown with in chat.
+    name: String,
+    /// Account the player logged in to, none for guests.
+    account: Option<AccountId>,
+    /// Party the player started the battle with.
+    party: Party<SavedPokemon>,
+    endpoint: Option<Endpoint>,
+    /// When the player lost their connection, if they have not rejoined since.
+    disconnected: Option<Instant>,
+    token: SessionToken,
+    incoming: Option<Sender<ClientMessage<Id>>>,
+    outgoing: Receiver<ServerMessage<Id>>,
+    /// Every message sent to this seat, serialized, so it can be resent on reconnect.
+    history: Vec<Vec<u8>>,
+    clock: SeatClock,
+    /// AI that follows along with the player, to know what they are asked and act for them if their clock runs out.
+    shadow: Option<Box<dyn BattleEndpoint<Id>>>,
+    /// Actions the shadow would take for what the player is currently being asked and has not answered yet.
+    pending: Vec<ClientMessage<Id>>,
+}
+
+impl Seat {
+    /// Create a seat and the battle endpoint that talks through it.
+    pub fn new(
+        player: usize,
+        seated: SeatPlayer,
+        clock: SeatClock,
+        shadow: Option<Box<dyn BattleEndpoint<Id>>>,
+    ) -> (Self, Box<BattleServerPlayer<Id>>) {
+        let (incoming, receiver) = crossbeam_channel::unbounded();
+        let (sender, outgoing) = crossbeam_channel::unbounded();
+        (
+            Self {
+                player,
+                name: seated.name,
+                account: seated.account,
+                party: seated.party,
+                endpoint: Some(seated.endpoint),
+                disconnected: None,
+                token: seated.token,
+                incoming: Some(incoming),
+                outgoing,
+                history: Default::default(),
+                clock,
+                shadow,
+                pending: Default::default(),
+            },
+            BattleServerPlayer::new(sender, receiver),
+        )
+    }
+
+    fn send(&self, sender: &PacketSender, message: &NetServerMessage<Id>) {
+        if let Some(endpoint) = self.endpoint {
+            sender.send(endpoint, serialize(message));
+        }
+    }
+
+    /// Give up the battle for the player and stop taking actions from them.
+    fn forfeit(&mut self) {
+        self.pending.clear();
+        if let Some(channel) = self.incoming.take() {
+            // Messages already sent are still received after the channel is dropped
+            if let Err(err) = channel.try_send(ClientMessage::Forfeit) {
+                log::error!("Could not send over channel with error {}", err);
+            }
+        }
+    }
+
+    /// Take an action the player was asked for,
+    /// stopping their clock once every action they are waited on for has arrived.
+    fn answer(&mut self, action: &ClientMessage<Id>, sender: &PacketSender) {
+        match action {
+            ClientMessage::Forfeit => self.pending.clear(),
+            action => {
+                if let Some(index) = self
+                    .pending
+                    .iter()
+                    .position(|pending| answers(action, pending))
+                {
+                    self.pending.remove(index);
+                }
+            }
+        }
+        if self.pending.is_empty() {
+            self.stop_clock(sender);
+        }
+    }
+
+    /// Stop the seat's clock and tell the player how much time they have left.
+    fn stop_clock(&mut self, sender: &PacketSender) {
+        if self.clock.running() {
+            self.clock.stop();
+            self.send(sender, &NetServerMessage::Clock(self.clock.state()));
+        }
+    }
+}
+
+impl<'d> BattleInstance<'d> {
+    pub fn new(
+        battle: ServerBattle<'d>,
+        format: BattleFormat,
+        random: StdRng,
+        seats: Vec<Seat>,
+        observed: Vec<Receiver<ServerMessage<Id>>>,
+        replay: Option<Replay<Id>>,
+    ) -> Self {
+        Self {
+            battle,
+            format,
+            random,
+            seats,
+            observed,
+            spectators: Default::default(),
+            spectator: Default::default(),
+            public: Default::default(),
+            replay,
+            started: Instant::now(),
+            winner: None,
+        }
+    }
+
+    /// Start the battle, telling everyone in it the format and the name of each player first.
+    pub fn begin(&mut self, players: Vec<Option<String>>, sender: &PacketSender) {
+        self.battle.begin();
+        self.setup(players, sender);
+        self.flush(sender);
+    }
+
+    pub fn update(
+        &mut self,
+        engine: &mut DefaultMoveEngine,
+        movedex: &'d BasicDex<Move>,
+        itemdex: &'d BasicDex<Item>,
+        sender: &PacketSender,
+    ) {
+        self.battle
+            .update(&mut self.random, engine, movedex, itemdex);
+        self.flush(sender);
+        self.check_clocks(sender);
+        self.remove_disconnected();
+    }
+
+    /// Pass a game message from an endpoint to its player.
+    pub fn receive(
+        &mut self,
+        endpoint: &Endpoint,
+        message: ClientMessage<Id>,
+        sender: &PacketSender,
+    ) {
+        match self.seat_mut(endpoint) {
+            Some(seat) => {
+                seat.answer(&message, sender);
+                if let Some(channel) = seat.incoming.as_ref() {
+                    if let Err(err) = channel.try_send(message) {
+                        log::error!("Could not send over channel with error {}", err);
+                    }
+                }
+            }
+            None => log::warn!("Could not find player for endpoint at {}", endpoint),
+        }
+    }
+
+    /// Move the seat holding `token` to a new endpoint and resend everything the seat was sent.
+    /// Returns the endpoint the seat was previously bound to.
+    pub fn rejoin(
+        &mut self,
+        token: SessionToken,
+        endpoint: Endpoint,
+        sender: &PacketSender,
+    ) -> Option<Option<Endpoint>> {
+        let seat = self
+            .seats
+            .iter_mut()
+            .find(|seat| seat.token == token && seat.incoming.is_some())?;
+        let previous = seat.endpoint.replace(endpoint);
+        seat.disconnected = None;
+        sender.send(
+            endpoint,
+            serialize(&NetServerMessage::<Id>::Validate(ConnectMessage::Rejoined)),
+        );
+        for message in seat.history.iter() {
+            sender.send(endpoint, message.clone());
+        }
+        if seat.clock.running() {
+            seat.send(sender, &NetServerMessage::Clock(seat.clock.state()));
+        }
+        Some(previous)
+    }
+
+    /// Add a spectator and send them every public message so far.
+    pub fn spectate(&mut self, id: BattleId, endpoint: Endpoint, sender: &PacketSender) {
+        sender.send(
+            endpoint,
+            serialize(&NetServerMessage::<Id>::Validate(
+                ConnectMessage::Spectating(id),
+            )),
+        );
+        for message in self.public.iter() {
+            sender.send(endpoint, message.clone());
+        }
+        self.spectators.push(endpoint);
+    }
+
+    pub fn remove_spectator(&mut self, endpoint: &Endpoint) {
+        self.spectators.retain(|spectator| spectator != endpoint);
+    }
+
+    /// Stop sending to the player, keeping their seat for them to rejoin for a while.
+    pub fn disconnect(&mut self, endpoint: &Endpoint) {
+        if let Some(seat) = self.seat_mut(endpoint) {
+            seat.endpoint = None;
+            seat.disconnected = Some(Instant::now());
+        }
+    }
+
+    /// Forfeit for a player that has left, so the battle goes on without them.
+    pub fn leave(&mut self, endpoint: &Endpoint) {
+        if let Some(seat) = self.seat_mut(endpoint) {
+            seat.endpoint = None;
+            seat.disconnected = None;
+            seat.forfeit();
+        }
+    }
+
+    pub fn endpoints(&self) -> impl Iterator<Item = &Endpoint> + '_ {
+        self.seats.iter().flat_map(|seat| seat.endpoint.as_ref())
+    }
+
+    /// Name of the player at the endpoint.
+    pub fn name(&self, endpoint: &Endpoint) -> Option<&str> {
+        self.seat(endpoint).map(|seat| seat.name.as_str())
+    }
+
+    /// Endpoint of the player logged in to the account.
+    pub fn endpoint_of(&self, account: &str) -> Option<Endpoint> {
+        self.seats
+            .iter()
+            .find(|seat| seat.account.as_deref() == Some(account))
+            .and_then(|seat| seat.endpoint)
+    }
+
+    /// Send a chat message to the battle's players and spectators.
+    pub fn chat(&self, message: &NetServerMessage<Id>, sender: &PacketSender) {
+        let bytes = serialize(message);
+        for endpoint in self.endpoints().chain(self.spectators.iter()) {
+            sender.send(*endpoint, bytes.clone());
+        }
+    }
+
+    pub fn end(&mut self, sender: &PacketSender) {
+        self.battle.end(None);
+        self.flush(sender);
+    }
+
+    pub fn finished(&self) -> bool {
+        self.battle.finished()
+    }
+
+    /// How the battle ended for each human player, once it has ended.
+    pub fn results(&self) -> Option<Vec<SeatResult>> {
+        let winner = self.winner?;
+        Some(
+            self.seats
+                .iter()
+                .map(|seat| {
+                    let result = match winner {
+                        Some(winner) if winner as usize == seat.player => MatchResult::Win,
+                        Some(..) => MatchResult::Loss,
+                        None => MatchResult::Draw,
+                    };
+                    SeatResult {
+                        name: seat.name.clone(),
+                        account: seat.account.clone(),
+                        result,
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// Players still connected, with what they need to sit in a battle again.
+    pub fn players(&self) -> Vec<SeatPlayer> {
+        self.seats
+            .iter()
+            .flat_map(|seat| {
+                seat.endpoint.map(|endpoint| SeatPlayer {
+                    endpoint,
+                    token: seat.token,
+                    name: seat.name.clone(),
+                    account: seat.account.clone(),
+                    party: seat.party.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// A rematch for the same players, if they are all still connected.
+    pub fn rematch(&self) -> Option<Rematch> {
+        let players = self.players();
+        match players.is_empty() || players.len() < self.seats.len() {
+            true => None,
+            false => Some(Rematch::new(self.format, players)),
+        }
+    }
+
+    pub fn take_replay(&mut self) -> Option<Replay<Id>> {
+        self.replay.take()
+    }
+
+    /// Forfeit for players that have not rejoined in time.
+    fn remove_disconnected(&mut self) {
+        for seat in self.seats.iter_mut() {
+            if seat
+                .disconnected
+                .filter(|since| since.elapsed() >= REJOIN_TIME)
+                .is_some()
+            {
+                log::info!("Player {} did not rejoin in time.", seat.player);
+                seat.disconnected = None;
+                seat.forfeit();
+            }
+        }
+    }
+
+    /// Act for players whose clocks have run out.
+    fn check_clocks(&mut self, sender: &PacketSender) {
+        for seat in self.seats.iter_mut() {
+            let expiry = match seat.clock.expired() {
+                Some(expiry) => expiry,
+                None => continue,
+            };
+            seat.stop_clock(sender);
+            seat.send(sender, &NetServerMessage::TimedOut);
+            let actions = match expiry {
+                Expiry::Turn
+                    if seat.clock.action() == TimeoutAction::Default
+                        && !seat.pending.is_empty() =>
+                {
+                    log::info!("Player {} ran out of time, acting for them.", seat.player);
+                    std::mem::take(&mut seat.pending)
+                }
+                _ => {
+                    log::info!("Player {} ran out of time, forfeiting.", seat.player);
+                    seat.pending.clear();
+                    vec![ClientMessage::Forfeit]
+                }
+            };
+            if let Some(channel) = seat.incoming.as_ref() {
+                for action in actions {
+                    if let Err(err) = channel.try_send(action) {
+                        log::error!("Could not send over channel with error {}", err);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Send the format and players to the seats and keep it for players rejoining and spectators.
+    fn setup(&mut self, players: Vec<Option<String>>, sender: &PacketSender) {
+        let format = self.format;
+        let setup = |you| {
+            serialize(&NetServerMessage::<Id>::Setup(BattleSetup {
+                format,
+                players: players.clone(),
+                you,
+            }))
+        };
+        for seat in self.seats.iter_mut() {
+            let bytes = setup(Some(seat.player as _));
+            if let Some(endpoint) = seat.endpoint {
+                sender.send(endpoint, bytes.clone());
+            }
+            seat.history.push(bytes);
+        }
+        self.public.push(setup(None));
+    }
+
+    /// Send messages the battle has queued for each seat.
+    /// Spectators are sent only what every player sees, gathered from all of the seats.
+    fn flush(&mut self, sender: &PacketSender) {
+        for (index, seat) in self.seats.iter_mut().enumerate() {
+            for message in seat.outgoing.try_iter() {
+                if let Some(shadow) = seat.shadow.as_mut() {
+                    shadow.send(message.clone());
+                    while let Ok(action) = shadow.receive() {
+                        seat.pending.push(action);
+                    }
+                }
+                if clock::awaits_action(&message) && seat.clock.start() {
+                    if let Some(endpoint) = seat.endpoint {
+                        sender.send(
+                            endpoint,
+                            serialize(&NetServerMessage::<Id>::Clock(seat.clock.state())),
+                        );
+                    }
+                }
+                if let ServerMessage::GameEnd(winner) = &message {
+                    self.winner = Some(*winner);
+                }
+                let public = self.spectator.public(index, &message);
+                let message = NetServerMessage::Game(message);
+                let bytes = serialize(&message);
+                if let Some(endpoint) = seat.endpoint {
+                    sender.send(endpoint, bytes.clone());
+                }
+                if let Some(public) = public {
+                    let bytes = serialize(&NetServerMessage::Game(public));
+                    for spectator in self.spectators.iter() {
+                        sender.send(*spectator, bytes.clone());
+                    }
+                    self.public.push(bytes);
+                }
+                seat.history.push(bytes);
+                if let (Some(replay), NetServerMessage::Game(message)) = (&mut self.replay, message)
+                {
+                    if let Some(player) = replay.players.get_mut(seat.player) {
+                        player.messages.push(ReplayMessage {
+                            time: self.started.elapsed().as_secs_f32(),
+                            message,
+                        });
+                    }
+                }
+            }
+        }
+        let seats = self.seats.len();
+        for (index, observed) in self.observed.iter().enumerate() {
+            for message in observed.try_iter() {
+                if let Some(public) = self.spectator.public(seats + index, &message) {
+                    let bytes = serialize(&NetServerMessage::Game(public));
+                    for spectator in self.spectators.iter() {
+                        sender.send(*spectator, bytes.clone());
+                    }
+                    self.public.push(bytes);
+                }
+            }
+        }
+    }
+
+    fn seat(&self, endpoint: &Endpoint) -> Option<&Seat> {
+        self.seats
+            .iter()
+            .find(|seat| seat.endpoint.as_ref() == Some(endpoint))
+    }
+
+    fn seat_mut(&mut self, endpoint: &Endpoint) -> Option<&mut Seat> {
+        self.seats
+            .iter_mut()
+            .find(|seat| seat.endpoint.as_ref() == Some(endpoint))
+    }
+}
+
+/// Whether an action from a player answers the same question as an action the shadow would take,
+/// which for moves and faint replacements means for the same active slot.
+fn answers(action: &ClientMessage<Id>, pending: &ClientMessage<Id>) -> bool {
+    match (action, pending) {
+        (ClientMessage::Move(slot, ..), ClientMessage::Move(pending, ..))
+        | (ClientMessage::ReplaceFaint(slot, ..), ClientMessage::ReplaceFaint(pending, ..)) => {
+            slot == pending
+        }
+        (action, pending) => std::mem::discriminant(action) == std::mem::discriminant(pending),
+    }
+}
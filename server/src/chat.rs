@@ -0,0 +1,87 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use common::chat::{ChatError, MAX_CHAT_LENGTH};
+
+use crate::{configuration::Configuration, net::Endpoint};
+
+/// Checks chat messages against the server's limits and word filter.
+pub struct Chat {
+    /// Most messages an endpoint can send within `interval`.
+    messages: usize,
+    interval: Duration,
+    /// Lowercase words that are masked out of messages.
+    filter: Vec<String>,
+    /// When each endpoint sent its recent messages.
+    sent: HashMap<Endpoint, VecDeque<Instant>>,
+}
+
+impl Chat {
+    pub fn new(configuration: &Configuration) -> Self {
+        Self {
+            messages: configuration.chat_messages as _,
+            interval: Duration::from_secs(configuration.chat_interval),
+            filter: configuration
+                .chat_filter
+                .iter()
+                .map(|word| word.to_lowercase())
+                .collect(),
+            sent: Default::default(),
+        }
+    }
+
+    /// Check that the endpoint can send a message, returning it with filtered words masked.
+    pub fn check(&mut self, endpoint: Endpoint, text: &str) -> Result<String, ChatError> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(ChatError::Empty);
+        }
+        if text.chars().count() > MAX_CHAT_LENGTH {
+            return Err(ChatError::TooLong);
+        }
+
+        let interval = self.interval;
+        let sent = self.sent.entry(endpoint).or_default();
+        while sent
+            .front()
+            .map(|time| time.elapsed() >= interval)
+            .unwrap_or_default()
+        {
+            sent.pop_front();
+        }
+        if sent.len() >= self.messages {
+            return Err(ChatError::TooFast);
+        }
+        sent.push_back(Instant::now());
+
+        Ok(self.mask(text))
+    }
+
+    pub fn remove(&mut self, endpoint: &Endpoint) {
+        self.sent.remove(endpoint);
+    }
+
+    /// Replace every filtered word in the text with asterisks.
+    fn mask(&self, text: &str) -> String {
+        let mut masked = String::with_capacity(text.len());
+        let mut word = String::new();
+        for c in text.chars().chain(std::iter::once(' ')) {
+            match c.is_alphanumeric() {
+                true => word.push(c),
+                false => {
+                    match self.filter.contains(&word.to_lowercase()) {
+                        true => masked.extend(word.chars().map(|_| '*')),
+                        false => masked.push_str(&word),
+                    }
+                    word.clear();
+                    masked.push(c);
+                }
+            }
+        }
+        // Remove the space added to end the last word
+        masked.pop();
+        masked
+    }
+}
@@ -0,0 +1,74 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use log::LevelFilter;
+
+use common::lobby::BattleFormat;
+
+use pokemon_battle_server::configuration::Configuration;
+
+/// Server for online pokemon battles.
+#[derive(Parser)]
+#[clap(version)]
+pub struct Args {
+    /// Path to the configuration file, defaults to config.toml in the working directory
+    #[clap(short, long, parse(from_os_str))]
+    pub config: Option<PathBuf>,
+    /// Port to listen on
+    #[clap(short, long)]
+    pub port: Option<u16>,
+    /// Number of active pokemon per player
+    #[clap(short, long)]
+    pub battle_size: Option<u8>,
+    /// Format of quick match battles: singles, doubles, triples or ffa followed by a player count
+    #[clap(short, long)]
+    pub format: Option<BattleFormat>,
+    /// Seed for every battle's random number generator
+    #[clap(short, long)]
+    pub seed: Option<u64>,
+    /// Number of player slots in each battle filled by AI
+    #[clap(short, long)]
+    pub ai: Option<u8>,
+    /// Dex bundle to load instead of the built in one
+    #[clap(long, parse(from_os_str))]
+    pub dex: Option<PathBuf>,
+    /// Battle move bundle to load instead of the built in one
+    #[clap(long, parse(from_os_str))]
+    pub battle: Option<PathBuf>,
+    /// Reload the bundles between battles when their files change
+    #[clap(long)]
+    pub reload: bool,
+    /// One of off, error, warn, info, debug or trace
+    #[clap(short, long)]
+    pub log_level: Option<LevelFilter>,
+}
+
+impl Args {
+    /// Override the configuration with any flags that were given.
+    pub fn apply(&self, configuration: &mut Configuration) {
+        if let Some(port) = self.port {
+            configuration.port = port;
+        }
+        if let Some(battle_size) = self.battle_size {
+            configuration.battle_size = Some(battle_size);
+        }
+        if let Some(format) = self.format {
+            configuration.format = Some(format);
+        }
+        if let Some(seed) = self.seed {
+            configuration.seed = Some(seed);
+        }
+        if let Some(ai) = self.ai {
+            configuration.ai = ai;
+        }
+        if let Some(dex) = &self.dex {
+            configuration.dex = Some(dex.clone());
+        }
+        if let Some(battle) = &self.battle {
+            configuration.battle = Some(battle.clone());
+        }
+        if self.reload {
+            configuration.reload = true;
+        }
+    }
+}
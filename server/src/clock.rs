@@ -0,0 +1,98 @@
+use std::time::{Duration, Instant};
+
+use common::{battle::message::ServerMessage, Clock, Id};
+
+use crate::configuration::TimeoutAction;
+
+/// If a message sent to a player waits on them to act, which starts their clock.
+pub fn awaits_action(message: &ServerMessage<Id>) -> bool {
+    matches!(
+        message,
+        ServerMessage::StartSelecting | ServerMessage::AskFinishedTurnQueue
+    )
+}
+
+/// Why a player's clock ran out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    /// The player took too long on one turn.
+    Turn,
+    /// The player used up all of their time for the battle.
+    Total,
+}
+
+/// Time a player has left to act, per turn and for the whole battle.
+pub struct SeatClock {
+    turn: Option<Duration>,
+    total: Option<Duration>,
+    /// When the clock was started, if it is running.
+    started: Option<Instant>,
+    /// What happens when the player runs out of time for a turn.
+    action: TimeoutAction,
+}
+
+impl SeatClock {
+    pub fn new(turn: Option<u64>, total: Option<u64>, action: TimeoutAction) -> Self {
+        Self {
+            turn: turn.map(Duration::from_secs),
+            total: total.map(Duration::from_secs),
+            started: None,
+            action,
+        }
+    }
+
+    pub fn action(&self) -> TimeoutAction {
+        self.action
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.turn.is_some() || self.total.is_some()
+    }
+
+    pub fn running(&self) -> bool {
+        self.started.is_some()
+    }
+
+    /// Start the clock, returning false if it was already running or has no limits.
+    pub fn start(&mut self) -> bool {
+        if !self.enabled() || self.running() {
+            return false;
+        }
+        self.started = Some(Instant::now());
+        true
+    }
+
+    /// Stop the clock, taking the time used from the player's total.
+    pub fn stop(&mut self) {
+        if let Some(started) = self.started.take() {
+            if let Some(total) = self.total.as_mut() {
+                *total = total.saturating_sub(started.elapsed());
+            }
+        }
+    }
+
+    pub fn expired(&self) -> Option<Expiry> {
+        let elapsed = self.started?.elapsed();
+        if self.total.map(|total| elapsed >= total).unwrap_or_default() {
+            Some(Expiry::Total)
+        } else if self.turn.map(|turn| elapsed >= turn).unwrap_or_default() {
+            Some(Expiry::Turn)
+        } else {
+            None
+        }
+    }
+
+    /// Time remaining, to tell the player about.
+    pub fn state(&self) -> Clock {
+        let elapsed = self
+            .started
+            .map(|started| started.elapsed())
+            .unwrap_or_default();
+        let remaining = |limit: Duration| limit.saturating_sub(elapsed).as_secs_f32();
+        Clock {
+            turn: self.turn.filter(|_| self.running()).map(remaining),
+            total: self.total.map(remaining),
+            running: self.running(),
+        }
+    }
+}
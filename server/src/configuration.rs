@@ -1,62 +1,241 @@
-use log::{error, info};
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 use std::{
-    fs::{read_to_string, write},
-    path::Path,
+    fs::read_to_string,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
 };
 
+use common::{lobby::BattleFormat, tournament::TournamentFormat};
+
 #[derive(Deserialize, Serialize)]
+#[serde(default)]
 pub struct Configuration {
+    /// Address to listen on, which can be IPv4 or IPv6.
+    /// Defaults to localhost, set it to `0.0.0.0` or `::` to accept players from other machines.
+    pub address: IpAddr,
     pub port: u16,
-    pub battle_size: u8,
-    // pub ai: u8,
+    /// Address to listen for WebRTC connections on, defaults to the next port on `address`.
+    pub webrtc_address: Option<SocketAddr>,
+    /// Address WebRTC clients are told to connect to, defaults to `webrtc_address`.
+    pub public_address: Option<SocketAddr>,
+    /// Number of active pokemon per player, checked against `format` if both are set.
+    pub battle_size: Option<u8>,
+    /// Format of quick match, ranked and tournament battles, one on one with `battle_size` active pokemon by default.
+    pub format: Option<BattleFormat>,
+    /// Number of player slots in each battle filled by AI.
+    pub ai: u8,
+    /// Seconds a lone player waits for an opponent before getting an AI opponent.
+    pub ai_timeout: Option<u64>,
+    /// Generate a random party for players that do not submit their own.
+    pub random_teams: bool,
+    /// Accept parties submitted by players.
+    pub custom_teams: bool,
+    /// Seconds without hearing from a player before they are considered disconnected.
+    pub timeout: u64,
+    /// Directory to save replays of finished battles to.
+    pub replays: Option<PathBuf>,
+    /// Dex bundle to load instead of the built in one.
+    pub dex: Option<PathBuf>,
+    /// Battle move bundle to load instead of the built in one.
+    pub battle: Option<PathBuf>,
+    /// Reload the bundles between battles when their files change.
+    pub reload: bool,
+    /// Seed every battle's random number generator with this instead of a random seed.
+    /// Parties generated for players are seeded with it as well.
+    pub seed: Option<u64>,
+    /// Seconds a player has to act each turn.
+    pub turn_time: Option<u64>,
+    /// Seconds a player has to act over the whole battle, like a chess clock.
+    /// Running out of this time forfeits the battle.
+    pub total_time: Option<u64>,
+    /// What happens when a player runs out of time for a turn.
+    pub timeout_action: TimeoutAction,
+    /// Most chat messages a player can send within `chat_interval` seconds.
+    pub chat_messages: u32,
+    pub chat_interval: u64,
+    /// Words masked out of chat messages, ignoring case.
+    pub chat_filter: Vec<String>,
+    /// File to keep player accounts in, accounts are disabled without one.
+    pub accounts: Option<PathBuf>,
+    /// Run a tournament between registered players instead of letting players find battles.
+    pub tournament: Option<TournamentSettings>,
+    /// Rules submitted parties are validated against.
+    pub rules: Rules,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub struct Rules {
+    /// Allow only one pokemon of each species per party.
+    pub species_clause: bool,
+    /// Allow only one of each held item per party.
+    pub item_clause: bool,
+    /// Highest level a submitted pokemon can be.
+    pub level_cap: u8,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct TournamentSettings {
+    pub format: TournamentFormat,
+    /// Names of the players taking part, best seed first.
+    pub players: Vec<String>,
+    /// Number of rounds in a Swiss tournament, enough to find a winner by default.
+    pub rounds: Option<u32>,
+    /// Seconds after the server starts to begin the first round, even if not everyone has joined.
+    #[serde(default = "TournamentSettings::default_start_after")]
+    pub start_after: u64,
+}
+
+impl TournamentSettings {
+    fn default_start_after() -> u64 {
+        300
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeoutAction {
+    /// Let an AI choose the player's action for them.
+    Default,
+    /// Forfeit the battle for the player.
+    Forfeit,
+}
+
+/// Addresses the server listens on and advertises.
+pub struct ListenAddresses {
+    pub session: SocketAddr,
+    pub webrtc: SocketAddr,
+    pub public: SocketAddr,
 }
 
 impl Configuration {
     const FILENAME: &'static str = "config.toml";
 
-    pub fn load() -> Self {
-        let path = match std::env::current_exe() {
-            Ok(ref path) => path
-                .parent()
-                .unwrap_or_else(|| panic!("Could not get parent directory of executable!")),
-            Err(err) => {
-                error!(
-                    "Could not get path of current executable with error {}\nUsing fallback path.",
-                    err
-                );
-                Path::new(".")
+    /// Resolve the addresses to listen on, checking that they can be used together.
+    pub fn addresses(&self) -> Result<ListenAddresses, String> {
+        let session = SocketAddr::new(self.address, self.port);
+
+        let webrtc = match self.webrtc_address {
+            Some(address) => address,
+            None => match self.port.checked_add(1) {
+                Some(port) => SocketAddr::new(self.address, port),
+                None => {
+                    return Err(format!(
+                        "Port {} leaves no port for WebRTC, set webrtc_address",
+                        self.port
+                    ))
+                }
+            },
+        };
+
+        if session.port() == 0 || webrtc.port() == 0 {
+            return Err("Ports must be set to something other than 0".to_owned());
+        }
+
+        if session.port() == webrtc.port() && overlaps(session.ip(), webrtc.ip()) {
+            return Err(format!(
+                "Address {} and WebRTC address {} use the same port",
+                session, webrtc
+            ));
+        }
+
+        let public = match self.public_address {
+            Some(public) => {
+                if public.ip().is_unspecified() || public.port() == 0 {
+                    return Err(format!("Public address {} cannot be connected to", public));
+                }
+                public
             }
+            None => {
+                if webrtc.ip().is_unspecified() {
+                    warn!("Set public_address for browser clients, WebRTC is on all interfaces");
+                }
+                webrtc
+            }
+        };
+
+        Ok(ListenAddresses {
+            session,
+            webrtc,
+            public,
+        })
+    }
+
+    /// Work out the format of battles the server starts itself, checking that it can be played.
+    pub fn format(&self) -> Result<BattleFormat, String> {
+        let format = match (self.format, self.battle_size) {
+            (Some(format), Some(size)) if format.active() != size as usize => {
+                return Err(format!(
+                "Battle size {} does not match format {}, which has {} active pokemon per player",
+                size,
+                format,
+                format.active()
+            ))
+            }
+            (Some(format), ..) => format,
+            (None, size) => {
+                let size = size.unwrap_or(1);
+                BattleFormat::from_active(size)
+                    .ok_or_else(|| format!("Battle size must be 1 to 3, not {}", size))?
+            }
+        };
+        format.validate()?;
+        if self.ai as usize >= format.players() {
+            return Err(format!(
+                "AI fills all {} player slots of {} battles",
+                format.players(),
+                format
+            ));
         }
-        .join(Self::FILENAME);
-        match read_to_string(&path) {
-            Ok(bytes) => toml::from_str(&bytes).unwrap_or_else(|err| {
-                panic!("Could not deserialize configuration with error: {}", err)
-            }),
-            Err(err) => {
-                error!(
-                    "Could not read configuration file at {:?} with error {}",
-                    path, err
+        Ok(format)
+    }
+
+    /// Load the configuration file at `path`, or `config.toml` in the working directory.
+    /// Without a path, a missing file means the defaults are used.
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigurationError> {
+        let (path, required) = match path {
+            Some(path) => (path, true),
+            None => (Path::new(Self::FILENAME), false),
+        };
+        match read_to_string(path) {
+            Ok(data) => {
+                toml::from_str(&data).map_err(|err| ConfigurationError::Parse(path.to_owned(), err))
+            }
+            Err(err) if !required && err.kind() == ErrorKind::NotFound => {
+                info!(
+                    "No configuration file found at {:?}, using the default configuration.",
+                    path
                 );
-                let configuration = Configuration::default();
-                write(
-                    &path,
-                    toml::to_string(&configuration).unwrap_or_else(|err| {
-                        panic!(
-                            "Could not serialize configuration with error {} \nThis is bad.",
-                            err
-                        )
-                    }),
-                )
-                .unwrap_or_else(|err| {
-                    panic!(
-                        "Could not write to configuration file at {:?} with error {}",
-                        path, err
-                    )
-                });
-                info!("Created a new configuration file.");
-                configuration
+                Ok(Self::default())
             }
+            Err(err) => Err(ConfigurationError::Read(path.to_owned(), err)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigurationError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+}
+
+impl std::error::Error for ConfigurationError {}
+
+impl core::fmt::Display for ConfigurationError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Read(path, err) => write!(
+                f,
+                "Could not read configuration file at {:?} with error {}",
+                path, err
+            ),
+            Self::Parse(path, err) => write!(
+                f,
+                "Could not parse configuration file at {:?} with error {}",
+                path, err
+            ),
         }
     }
 }
@@ -64,9 +243,46 @@ impl Configuration {
 impl Default for Configuration {
     fn default() -> Self {
         Self {
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
             port: common::DEFAULT_PORT,
-            battle_size: 1,
-            // ai: 0,
+            webrtc_address: None,
+            public_address: None,
+            battle_size: None,
+            format: None,
+            ai: 0,
+            ai_timeout: None,
+            random_teams: true,
+            custom_teams: true,
+            timeout: 30,
+            replays: None,
+            dex: None,
+            battle: None,
+            reload: false,
+            seed: None,
+            turn_time: None,
+            total_time: None,
+            timeout_action: TimeoutAction::Default,
+            chat_messages: 5,
+            chat_interval: 10,
+            chat_filter: Vec::new(),
+            accounts: None,
+            tournament: None,
+            rules: Default::default(),
         }
     }
 }
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            species_clause: true,
+            item_clause: false,
+            level_cap: 50,
+        }
+    }
+}
+
+/// Whether two listening addresses could bind the same interface.
+fn overlaps(a: IpAddr, b: IpAddr) -> bool {
+    a == b || a.is_unspecified() || b.is_unspecified()
+}
@@ -0,0 +1,122 @@
+use std::{
+    collections::HashMap,
+    fs::{metadata, read},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use log::{info, warn};
+use rand::prelude::StdRng;
+use serde::de::DeserializeOwned;
+
+use common::{
+    battle::{engine::default::moves::MoveExecution, prelude::DefaultMoveEngine},
+    deserialize,
+    hash::hash,
+    pokedex::{
+        item::Item,
+        moves::{Move, MoveId},
+        pokemon::Pokemon,
+        BasicDex,
+    },
+    BundleHash, Id,
+};
+
+pub type Dexes = (BasicDex<Pokemon>, BasicDex<Move>, BasicDex<Item>);
+
+type BattleMoves = (HashMap<MoveId, MoveExecution>, HashMap<MoveId, String>);
+
+/// Dex bundle built into the binary.
+const DEX: &[u8] = include_bytes!("../../dex.bin");
+/// Battle move bundle built into the binary.
+const BATTLE: &[u8] = include_bytes!("../../battle.bin");
+
+/// Dex and battle bundles, loaded from files if they are given and from the binary otherwise.
+pub struct Bundles {
+    dex: Option<PathBuf>,
+    battle: Option<PathBuf>,
+    /// When the bundle files were last modified as of the last load.
+    modified: [Option<SystemTime>; 2],
+    /// Hashes of the bundles that were last loaded.
+    pub hash: BundleHash,
+}
+
+impl Bundles {
+    pub fn new(dex: Option<PathBuf>, battle: Option<PathBuf>) -> Self {
+        let mut bundles = Self {
+            dex,
+            battle,
+            modified: Default::default(),
+            hash: BundleHash::BUILT,
+        };
+        bundles.modified = bundles.modified();
+        bundles
+    }
+
+    /// Load the dexes, falling back to the built in ones if the file cannot be read.
+    pub fn dexes(&mut self) -> Dexes {
+        let (dexes, hash) = load(self.dex.as_deref(), DEX, "dex");
+        self.hash.dex = hash;
+        dexes
+    }
+
+    /// Load the battle moves into a move engine, falling back to the built in ones if the file cannot be read.
+    pub fn engine(&mut self) -> DefaultMoveEngine {
+        let ((moves, scripts), hash) =
+            load::<BattleMoves>(self.battle.as_deref(), BATTLE, "battle");
+        self.hash.battle = hash;
+
+        let mut engine = DefaultMoveEngine::new::<Id, StdRng>();
+
+        engine.scripting.scripts = scripts;
+
+        engine.moves = moves;
+
+        engine
+    }
+
+    /// Whether a bundle file has been modified since it was last checked.
+    pub fn changed(&mut self) -> bool {
+        let modified = self.modified();
+        let changed = modified != self.modified;
+        self.modified = modified;
+        changed
+    }
+
+    fn modified(&self) -> [Option<SystemTime>; 2] {
+        let modified = |path: &Option<PathBuf>| {
+            path.as_ref()
+                .and_then(|path| metadata(path).and_then(|data| data.modified()).ok())
+        };
+        [modified(&self.dex), modified(&self.battle)]
+    }
+}
+
+/// Load a bundle and hash its contents.
+fn load<T: DeserializeOwned>(path: Option<&Path>, embedded: &[u8], name: &str) -> (T, u64) {
+    if let Some(path) = path {
+        match read(path) {
+            Ok(bytes) => match deserialize(&bytes) {
+                Ok(data) => {
+                    info!("Loaded {} bundle from {:?}", name, path);
+                    return (data, hash(&bytes));
+                }
+                Err(err) => warn!(
+                    "Could not deserialize {} bundle at {:?} with error {}, using the built in one.",
+                    name, path, err
+                ),
+            },
+            Err(err) => warn!(
+                "Could not read {} bundle at {:?} with error {}, using the built in one.",
+                name, path, err
+            ),
+        }
+    }
+    let data = deserialize(embedded).unwrap_or_else(|err| {
+        panic!(
+            "Could not deserialize built in {} bundle with error {}",
+            name, err
+        )
+    });
+    (data, hash(embedded))
+}
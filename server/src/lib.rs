@@ -0,0 +1,29 @@
+//! Server for online pokemon battles, independent of how packets reach it.
+
+extern crate firecore_battle_net as common;
+
+use common::serialize as serialize2;
+
+mod accounts;
+mod battle;
+mod chat;
+mod clock;
+pub mod configuration;
+mod data;
+mod lobby;
+pub mod loopback;
+mod manager;
+pub mod net;
+mod player;
+mod ranked;
+mod rematch;
+mod server;
+mod spectator;
+mod tournament;
+mod validation;
+
+pub use server::Server;
+
+fn serialize(s: &impl serde::Serialize) -> Vec<u8> {
+    serialize2(s).unwrap()
+}
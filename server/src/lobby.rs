@@ -0,0 +1,236 @@
+use std::collections::{BTreeMap, HashMap};
+
+use common::lobby::{
+    BattleFormat, LobbyError, RoomId, RoomInfo, RoomMember, RoomSettings, RoomState, MAX_ROOM_NAME,
+};
+
+use crate::net::Endpoint;
+
+/// Rooms players wait in until everyone in them is ready to battle.
+#[derive(Default)]
+pub struct Lobby {
+    rooms: BTreeMap<RoomId, Room>,
+    /// Room each endpoint is in.
+    members: HashMap<Endpoint, RoomId>,
+    next_id: RoomId,
+}
+
+struct Room {
+    settings: RoomSettings,
+    capacity: u8,
+    /// Members in order of joining, the first of which owns the room.
+    members: Vec<Member>,
+}
+
+struct Member {
+    endpoint: Endpoint,
+    name: String,
+    ready: bool,
+}
+
+impl Lobby {
+    pub fn rooms(&self) -> Vec<RoomInfo> {
+        self.rooms.iter().map(|(id, room)| room.info(*id)).collect()
+    }
+
+    pub fn room_of(&self, endpoint: &Endpoint) -> Option<RoomId> {
+        self.members.get(endpoint).copied()
+    }
+
+    /// Endpoints of everyone in a room.
+    pub fn members(&self, id: RoomId) -> Vec<Endpoint> {
+        self.rooms
+            .get(&id)
+            .map(|room| room.members.iter().map(|member| member.endpoint).collect())
+            .unwrap_or_default()
+    }
+
+    /// Create a room owned by the endpoint, which must not be in a room.
+    pub fn create(
+        &mut self,
+        endpoint: Endpoint,
+        name: String,
+        settings: RoomSettings,
+        capacity: u8,
+    ) -> Result<RoomId, LobbyError> {
+        let settings = RoomSettings {
+            name: settings.name.trim().to_owned(),
+            ..settings
+        };
+        if settings.name.is_empty() || settings.name.chars().count() > MAX_ROOM_NAME {
+            return Err(LobbyError::InvalidName);
+        }
+        if settings.format.validate().is_err() {
+            return Err(LobbyError::InvalidFormat);
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.rooms.insert(
+            id,
+            Room {
+                settings,
+                capacity,
+                members: vec![Member::new(endpoint, name)],
+            },
+        );
+        self.members.insert(endpoint, id);
+        Ok(id)
+    }
+
+    /// Add the endpoint, which must not be in a room, to a room.
+    pub fn join(
+        &mut self,
+        id: RoomId,
+        endpoint: Endpoint,
+        name: String,
+        password: Option<String>,
+    ) -> Result<(), LobbyError> {
+        let room = self.rooms.get_mut(&id).ok_or(LobbyError::RoomNotFound)?;
+        if room.settings.password.is_some() && room.settings.password != password {
+            return Err(LobbyError::WrongPassword);
+        }
+        if room.members.len() >= room.capacity as usize {
+            return Err(LobbyError::RoomFull);
+        }
+        room.members.push(Member::new(endpoint, name));
+        self.members.insert(endpoint, id);
+        Ok(())
+    }
+
+    /// Remove the endpoint from its room, returning the room if it still has members.
+    pub fn leave(&mut self, endpoint: &Endpoint) -> Option<RoomId> {
+        let id = self.members.remove(endpoint)?;
+        let room = self.rooms.get_mut(&id)?;
+        room.members.retain(|member| &member.endpoint != endpoint);
+        match room.members.is_empty() {
+            true => {
+                self.rooms.remove(&id);
+                None
+            }
+            false => Some(id),
+        }
+    }
+
+    pub fn ready(&mut self, endpoint: &Endpoint, ready: bool) -> Result<RoomId, LobbyError> {
+        let id = self.room_of(endpoint).ok_or(LobbyError::NotInRoom)?;
+        if let Some(member) = self
+            .rooms
+            .get_mut(&id)
+            .and_then(|room| room.member_mut(endpoint))
+        {
+            member.ready = ready;
+        }
+        Ok(id)
+    }
+
+    /// Remove a member from the owner's room, returning the room and the kicked endpoint.
+    pub fn kick(&mut self, owner: &Endpoint, index: u8) -> Result<(RoomId, Endpoint), LobbyError> {
+        let id = self.room_of(owner).ok_or(LobbyError::NotInRoom)?;
+        let room = self.rooms.get(&id).ok_or(LobbyError::NotInRoom)?;
+        if room.owner() != Some(owner) {
+            return Err(LobbyError::NotOwner);
+        }
+        let kicked = room
+            .members
+            .get(index as usize)
+            .map(|member| member.endpoint)
+            .filter(|kicked| kicked != owner)
+            .ok_or(LobbyError::NotInRoom)?;
+        self.leave(&kicked);
+        Ok((id, kicked))
+    }
+
+    /// The state of a room as seen by each of its members.
+    pub fn states(&self, id: RoomId) -> Vec<(Endpoint, RoomState)> {
+        let room = match self.rooms.get(&id) {
+            Some(room) => room,
+            None => return Vec::new(),
+        };
+        let members = room
+            .members
+            .iter()
+            .map(|member| RoomMember {
+                name: member.name.clone(),
+                ready: member.ready,
+            })
+            .collect::<Vec<_>>();
+        room.members
+            .iter()
+            .enumerate()
+            .map(|(index, member)| {
+                (
+                    member.endpoint,
+                    RoomState {
+                        info: room.info(id),
+                        members: members.clone(),
+                        owner: 0,
+                        you: index as _,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Close rooms that are full and where everyone is ready, returning who is in them.
+    pub fn take_ready(&mut self) -> Vec<(RoomId, BattleFormat, Vec<Endpoint>)> {
+        let ready = self
+            .rooms
+            .iter()
+            .filter(|(.., room)| room.ready())
+            .map(|(id, ..)| *id)
+            .collect::<Vec<_>>();
+        let mut rooms = Vec::with_capacity(ready.len());
+        for id in ready {
+            if let Some(room) = self.rooms.remove(&id) {
+                let endpoints = room
+                    .members
+                    .iter()
+                    .map(|member| member.endpoint)
+                    .collect::<Vec<_>>();
+                for endpoint in endpoints.iter() {
+                    self.members.remove(endpoint);
+                }
+                rooms.push((id, room.settings.format, endpoints));
+            }
+        }
+        rooms
+    }
+}
+
+impl Room {
+    fn info(&self, id: RoomId) -> RoomInfo {
+        RoomInfo {
+            id,
+            name: self.settings.name.clone(),
+            format: self.settings.format,
+            players: self.members.len() as _,
+            capacity: self.capacity,
+            locked: self.settings.password.is_some(),
+        }
+    }
+
+    fn owner(&self) -> Option<&Endpoint> {
+        self.members.first().map(|member| &member.endpoint)
+    }
+
+    fn member_mut(&mut self, endpoint: &Endpoint) -> Option<&mut Member> {
+        self.members
+            .iter_mut()
+            .find(|member| &member.endpoint == endpoint)
+    }
+
+    fn ready(&self) -> bool {
+        self.members.len() >= self.capacity as usize
+            && self.members.iter().all(|member| member.ready)
+    }
+}
+
+impl Member {
+    fn new(endpoint: Endpoint, name: String) -> Self {
+        Self {
+            endpoint,
+            name,
+            ready: false,
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    rc::Rc,
+};
+
+use crate::net::{Endpoint, Transport};
+
+/// Transport that keeps packets in memory, so a server and its clients can run in one process.
+/// Packets cannot be lost in memory, so they are passed on without reliable channels.
+#[derive(Clone, Default)]
+pub struct Loopback(Rc<RefCell<Queues>>);
+
+#[derive(Default)]
+struct Queues {
+    /// Payloads sent by clients, in the order they were sent.
+    incoming: VecDeque<(Endpoint, Vec<u8>)>,
+    /// Payloads sent to each client.
+    outgoing: HashMap<Endpoint, VecDeque<Vec<u8>>>,
+}
+
+/// A client's end of a loopback transport.
+pub struct LoopbackClient {
+    endpoint: Endpoint,
+    queues: Rc<RefCell<Queues>>,
+}
+
+impl Loopback {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Connect a client that sends from the endpoint.
+    pub fn client(&self, endpoint: Endpoint) -> LoopbackClient {
+        LoopbackClient {
+            endpoint,
+            queues: self.0.clone(),
+        }
+    }
+}
+
+impl Transport for Loopback {
+    fn send(&self, endpoint: Endpoint, bytes: Vec<u8>) {
+        self.0
+            .borrow_mut()
+            .outgoing
+            .entry(endpoint)
+            .or_default()
+            .push_back(bytes);
+    }
+
+    fn receive(&self) -> Option<(Endpoint, Vec<u8>)> {
+        self.0.borrow_mut().incoming.pop_front()
+    }
+
+    fn update(&self, _delta: f32) {}
+
+    fn disconnect(&self, _endpoint: &Endpoint) {}
+}
+
+impl LoopbackClient {
+    pub fn endpoint(&self) -> Endpoint {
+        self.endpoint
+    }
+
+    pub fn send(&self, bytes: Vec<u8>) {
+        self.queues
+            .borrow_mut()
+            .incoming
+            .push_back((self.endpoint, bytes));
+    }
+
+    /// Receive the next payload the server sent to this client.
+    pub fn receive(&self) -> Option<Vec<u8>> {
+        self.queues
+            .borrow_mut()
+            .outgoing
+            .get_mut(&self.endpoint)
+            .and_then(VecDeque::pop_front)
+    }
+}
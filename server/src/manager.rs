@@ -0,0 +1,1062 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{create_dir_all, write},
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use log::{error, info, warn};
+use rand::{
+    prelude::{StdRng, ThreadRng},
+    Rng, SeedableRng,
+};
+
+use common::{
+    account::LoginError,
+    battle::prelude::{Battle, BattleData, BattleType, DefaultMoveEngine, PlayerData},
+    chat::{ChatError, ChatMessage, ChatScope},
+    lobby::{BattleFormat, LobbyError, LobbyMessage, LobbyRequest, RoomId},
+    pokedex::{
+        item::Item,
+        moves::Move,
+        pokemon::{owned::SavedPokemon, party::Party, Pokemon},
+        BasicDex, Dex,
+    },
+    ranking::{RankingMessage, RankingRequest},
+    replay::{Replay, ReplayPlayer, REPLAY_EXTENSION},
+    BattleId, BundleHash, ConnectMessage, Id, NetClientMessage, NetServerMessage, Player,
+    ProtocolRange, RematchMessage, RematchVote, SessionToken,
+};
+
+use crate::{
+    accounts::{AccountId, Accounts},
+    battle::{BattleInstance, Seat, SeatPlayer},
+    chat::Chat,
+    clock::SeatClock,
+    configuration::Configuration,
+    lobby::Lobby,
+    net::*,
+    player::{ai_player, generate_party, ObservedEndpoint},
+    ranked::RankedQueue,
+    rematch::{Rematch, RematchPlayer, REMATCH_TIME},
+    serialize,
+    tournament::Tournament,
+    validation::validate_party,
+};
+
+/// Pairs waiting players into battles and routes packets to the battle an endpoint is in.
+pub struct BattleManager<'d> {
+    pokedex: &'d BasicDex<Pokemon>,
+    movedex: &'d BasicDex<Move>,
+    itemdex: &'d BasicDex<Item>,
+    engine: DefaultMoveEngine,
+    /// Hashes of the bundles the dexes and engine were loaded from.
+    bundles: BundleHash,
+    /// Used for session tokens, battles have their own.
+    random: ThreadRng,
+    /// Used for the parties generated for players, seeded with the configured seed
+    /// so that players joining in the same order get the same parties.
+    parties: StdRng,
+    sender: PacketSender,
+    configuration: Configuration,
+    /// Format of quick match, ranked and tournament battles.
+    format: BattleFormat,
+
+    waiting: HashMap<Endpoint, WaitingPlayer>,
+    /// Endpoints waiting for a quick match, in order of joining.
+    queue: VecDeque<(Endpoint, Instant)>,
+    ranked_queue: RankedQueue,
+    lobby: Lobby,
+    chat: Chat,
+    /// Player accounts, if they are enabled.
+    accounts: Option<Accounts>,
+
+    battles: HashMap<BattleId, BattleInstance<'d>>,
+    routes: HashMap<Endpoint, BattleId>,
+    spectating: HashMap<Endpoint, BattleId>,
+    next_id: BattleId,
+    /// Battles that change the ratings of their players.
+    ranked: HashSet<BattleId>,
+    /// Rematches offered to the players of finished battles.
+    rematches: Vec<Rematch>,
+    /// Tournament the server is running, if it was configured with one.
+    tournament: Option<Tournament>,
+
+    /// When each endpoint was last heard from.
+    last_seen: HashMap<Endpoint, Instant>,
+}
+
+struct WaitingPlayer {
+    token: SessionToken,
+    player: Option<Player>,
+    /// Account the player logged in to, none for guests.
+    account: Option<AccountId>,
+    party: Option<Party<SavedPokemon>>,
+}
+
+impl WaitingPlayer {
+    /// Wait in the lobby again after a battle.
+    fn returning(player: SeatPlayer) -> Self {
+        Self {
+            token: player.token,
+            player: Some(Player {
+                name: player.name,
+                password: None,
+            }),
+            account: player.account,
+            party: Some(player.party),
+        }
+    }
+
+    fn ready(&self) -> bool {
+        self.player.is_some() && self.party.is_some()
+    }
+}
+
+impl<'d> BattleManager<'d> {
+    pub fn new(
+        pokedex: &'d BasicDex<Pokemon>,
+        movedex: &'d BasicDex<Move>,
+        itemdex: &'d BasicDex<Item>,
+        engine: DefaultMoveEngine,
+        bundles: BundleHash,
+        sender: PacketSender,
+        configuration: Configuration,
+    ) -> Result<Self, String> {
+        let accounts = match configuration.accounts.clone() {
+            Some(path) => Some(Accounts::load(path)?),
+            None => None,
+        };
+        let format = configuration.format()?;
+        let tournament = match configuration.tournament.as_ref() {
+            Some(..) if format.players() != 2 => {
+                return Err(format!(
+                    "Tournaments cannot be played in {} battles",
+                    format
+                ))
+            }
+            Some(settings) => Some(Tournament::new(settings)?),
+            None => None,
+        };
+        Ok(Self {
+            pokedex,
+            movedex,
+            itemdex,
+            engine,
+            bundles,
+            random: rand::thread_rng(),
+            parties: match configuration.seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            },
+            sender,
+            chat: Chat::new(&configuration),
+            configuration,
+            format,
+            waiting: Default::default(),
+            queue: Default::default(),
+            ranked_queue: Default::default(),
+            lobby: Default::default(),
+            battles: Default::default(),
+            routes: Default::default(),
+            spectating: Default::default(),
+            next_id: 0,
+            ranked: Default::default(),
+            rematches: Default::default(),
+            last_seen: Default::default(),
+            accounts,
+            tournament,
+        })
+    }
+
+    pub fn receive(&mut self, endpoint: Endpoint, message: NetClientMessage<Id>) {
+        self.last_seen.insert(endpoint, Instant::now());
+        match message {
+            NetClientMessage::RequestJoin(protocol, bundles) => {
+                if self.routes.contains_key(&endpoint) {
+                    return self.validate(endpoint, ConnectMessage::InProgress);
+                }
+                let supported = ProtocolRange::supported();
+                if !supported.contains(protocol) {
+                    info!(
+                        "Player at {} has protocol version {} but versions {} to {} are supported",
+                        endpoint, protocol, supported.min, supported.max
+                    );
+                    return self.validate(endpoint, ConnectMessage::WrongVersion(supported));
+                }
+                if bundles != self.bundles {
+                    info!(
+                        "Player at {} has different bundles {:?} than the server's {:?}",
+                        endpoint, bundles, self.bundles
+                    );
+                    return self.validate(endpoint, ConnectMessage::WrongBundles);
+                }
+                let party = match self.configuration.random_teams {
+                    true => Some(generate_party(&mut self.parties, self.pokedex.len() as _)),
+                    false => None,
+                };
+                if self
+                    .waiting
+                    .insert(
+                        endpoint,
+                        WaitingPlayer {
+                            token: self.random.gen(),
+                            player: None,
+                            account: None,
+                            party: party.clone(),
+                        },
+                    )
+                    .is_some()
+                {
+                    warn!(
+                        "Player at {} was replaced with another connection!",
+                        endpoint
+                    );
+                    self.dequeue(&endpoint);
+                    self.leave_room(&endpoint);
+                } else {
+                    info!("Player joined at {}", endpoint);
+                }
+                self.validate(endpoint, ConnectMessage::CanJoin(party));
+            }
+            NetClientMessage::Team(party) => {
+                if !self.configuration.custom_teams {
+                    return self.validate(endpoint, ConnectMessage::TeamRejected);
+                }
+                let problems = validate_party(
+                    &self.configuration.rules,
+                    self.pokedex,
+                    self.movedex,
+                    self.itemdex,
+                    &party,
+                );
+                match self.waiting.get_mut(&endpoint) {
+                    Some(waiting) => match problems.is_empty() {
+                        true => {
+                            waiting.party = Some(party);
+                            self.validate(endpoint, ConnectMessage::TeamAccepted);
+                        }
+                        false => {
+                            info!(
+                                "Rejected team submitted by player at {} with {} problem(s)",
+                                endpoint,
+                                problems.len()
+                            );
+                            self.validate(endpoint, ConnectMessage::IllegalTeam(problems));
+                        }
+                    },
+                    None => match self.routes.contains_key(&endpoint) {
+                        true => self.validate(endpoint, ConnectMessage::InProgress),
+                        false => self.validate(endpoint, ConnectMessage::NoRequest),
+                    },
+                }
+            }
+            NetClientMessage::Join(mut player) => match self.waiting.get_mut(&endpoint) {
+                Some(waiting) => {
+                    let login = match self.tournament.as_ref() {
+                        Some(tournament) if !tournament.registered(&player.name) => {
+                            Err(LoginError::NotRegistered)
+                        }
+                        _ => match self.accounts.as_mut() {
+                            Some(accounts) => {
+                                accounts.login(&player.name, player.password.as_deref())
+                            }
+                            None => Ok(None),
+                        },
+                    };
+                    // Passwords are not kept once they have been checked
+                    player.password = None;
+                    match login {
+                        Ok(account) => {
+                            if let Some(account) = account.as_ref() {
+                                info!("Player at {} logged in as {}", endpoint, account);
+                            }
+                            waiting.player = Some(player);
+                            waiting.account = account;
+                            let token = waiting.token;
+                            self.validate(endpoint, ConnectMessage::Session(token));
+                            if let Some(tournament) = self.tournament.as_ref() {
+                                self.sender.send(
+                                    endpoint,
+                                    serialize(&NetServerMessage::<Id>::Tournament(
+                                        tournament.bracket(),
+                                    )),
+                                );
+                            }
+                        }
+                        Err(err) => {
+                            info!(
+                                "Player at {} could not log in as {}: {}",
+                                endpoint, player.name, err
+                            );
+                            self.validate(endpoint, ConnectMessage::LoginFailed(err));
+                        }
+                    }
+                }
+                None => match self.routes.contains_key(&endpoint) {
+                    true => self.validate(endpoint, ConnectMessage::InProgress),
+                    false => self.validate(endpoint, ConnectMessage::AlreadyConnected),
+                },
+            },
+            NetClientMessage::Rejoin(token) => {
+                let sender = &self.sender;
+                let rejoined = self.battles.iter_mut().find_map(|(id, instance)| {
+                    instance
+                        .rejoin(token, endpoint, sender)
+                        .map(|previous| (*id, previous))
+                });
+                match rejoined {
+                    Some((id, previous)) => {
+                        info!("Player at {} rejoined battle {}", endpoint, id);
+                        if let Some(previous) = previous.filter(|previous| previous != &endpoint) {
+                            self.routes.remove(&previous);
+                            self.last_seen.remove(&previous);
+                            self.sender.disconnect(&previous);
+                        }
+                        self.routes.insert(endpoint, id);
+                    }
+                    None => self.validate(endpoint, ConnectMessage::UnknownSession),
+                }
+            }
+            NetClientMessage::Spectate(id) => {
+                let battle = match id {
+                    Some(id) => self.battles.get_mut(&id).map(|instance| (id, instance)),
+                    None => self
+                        .battles
+                        .iter_mut()
+                        .min_by_key(|(id, ..)| **id)
+                        .map(|(id, instance)| (*id, instance)),
+                };
+                match battle {
+                    Some((id, instance)) => {
+                        info!("Spectator at {} is watching battle {}", endpoint, id);
+                        instance.spectate(id, endpoint, &self.sender);
+                        if let Some(previous) = self.spectating.insert(endpoint, id) {
+                            if previous != id {
+                                if let Some(instance) = self.battles.get_mut(&previous) {
+                                    instance.remove_spectator(&endpoint);
+                                }
+                            }
+                        }
+                    }
+                    None => self.validate(endpoint, ConnectMessage::NoBattle),
+                }
+            }
+            NetClientMessage::Game(message) => {
+                let battles = &mut self.battles;
+                match self
+                    .routes
+                    .get(&endpoint)
+                    .and_then(|id| battles.get_mut(id))
+                {
+                    Some(instance) => instance.receive(&endpoint, message, &self.sender),
+                    None => warn!("Endpoint at {} is sending game messages", endpoint),
+                }
+            }
+            NetClientMessage::Lobby(request) => self.lobby(endpoint, request),
+            NetClientMessage::Chat(text) => self.chat(endpoint, &text),
+            NetClientMessage::Ranking(request) => {
+                let message = match request {
+                    RankingRequest::Leaderboard => RankingMessage::Leaderboard(
+                        self.accounts
+                            .as_ref()
+                            .map(Accounts::leaderboard)
+                            .unwrap_or_default(),
+                    ),
+                    RankingRequest::History(name) => {
+                        let rating = self
+                            .accounts
+                            .as_ref()
+                            .and_then(|accounts| accounts.rating(&name))
+                            .cloned();
+                        RankingMessage::History(name, rating)
+                    }
+                };
+                send_ranking(&self.sender, endpoint, message);
+            }
+            NetClientMessage::Stats(name) => {
+                let stats = self
+                    .accounts
+                    .as_ref()
+                    .and_then(|accounts| accounts.stats(&name));
+                self.sender.send(
+                    endpoint,
+                    serialize(&NetServerMessage::<Id>::Stats(name, stats)),
+                );
+            }
+            NetClientMessage::Rematch(vote) => {
+                match self
+                    .rematches
+                    .iter_mut()
+                    .find(|rematch| rematch.contains(&endpoint))
+                {
+                    Some(rematch) => {
+                        rematch.vote(&endpoint, vote);
+                        let votes = rematch.votes();
+                        for endpoint in rematch.endpoints() {
+                            send_rematch(
+                                &self.sender,
+                                *endpoint,
+                                RematchMessage::Votes(votes.clone()),
+                            );
+                        }
+                    }
+                    None => warn!(
+                        "Endpoint at {} voted on a rematch it was not offered",
+                        endpoint
+                    ),
+                }
+            }
+            NetClientMessage::Ping => self
+                .sender
+                .send(endpoint, serialize(&NetServerMessage::<Id>::Pong)),
+            NetClientMessage::Leave => {
+                info!("Player left at {}", endpoint);
+                self.last_seen.remove(&endpoint);
+                self.sender.disconnect(&endpoint);
+                self.chat.remove(&endpoint);
+                self.decline_rematch(&endpoint);
+                self.remove_waiting(&endpoint);
+                self.stop_spectating(&endpoint);
+                if let Some(id) = self.routes.remove(&endpoint) {
+                    if let Some(instance) = self.battles.get_mut(&id) {
+                        info!("Player at {} forfeited battle {} by leaving.", endpoint, id);
+                        instance.leave(&endpoint);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Start battles for waiting players and update running ones.
+    pub fn update(&mut self) {
+        self.disconnect_silent();
+
+        let humans = self.humans(self.format);
+
+        while self.queue.len() >= humans {
+            let players = self
+                .queue
+                .drain(..humans)
+                .map(|(endpoint, ..)| endpoint)
+                .collect::<Vec<_>>();
+            self.start(players, self.format);
+        }
+
+        for players in self.ranked_queue.take_pairs() {
+            let id = self.start(players.to_vec(), self.format);
+            info!("Battle {} is ranked.", id);
+            self.ranked.insert(id);
+        }
+
+        for (room, format, players) in self.lobby.take_ready() {
+            info!("Everyone in room {} is ready.", room);
+            self.start(players, format);
+        }
+
+        // Give players that have waited too long an AI opponent
+
+        if let Some(timeout) = self.configuration.ai_timeout.map(Duration::from_secs) {
+            while let Some((endpoint, ..)) = self
+                .queue
+                .front()
+                .filter(|(.., since)| since.elapsed() >= timeout)
+                .copied()
+            {
+                self.queue.pop_front();
+                info!(
+                    "Player at {} has waited for an opponent for {} seconds, filling with AI.",
+                    endpoint,
+                    timeout.as_secs()
+                );
+                self.start(vec![endpoint], self.format);
+            }
+        }
+
+        let routes = &mut self.routes;
+        let spectating = &mut self.spectating;
+        let replays = self.configuration.replays.as_deref();
+        let accounts = &mut self.accounts;
+        let ranked = &mut self.ranked;
+        let tournament = &mut self.tournament;
+        // Tournament players wait for their next match after each battle
+        let mut returning = Vec::new();
+
+        for (id, instance) in self.battles.iter_mut() {
+            instance.update(&mut self.engine, self.movedex, self.itemdex, &self.sender);
+            if instance.finished() {
+                info!("Battle {} has finished.", id);
+                routes.retain(|_, battle| *battle != *id);
+                spectating.retain(|_, battle| *battle != *id);
+                if let (Some(directory), Some(replay)) = (replays, instance.take_replay()) {
+                    save_replay(directory, *id, &replay);
+                }
+                let is_ranked = ranked.remove(id);
+                if let (Some(accounts), Some(results)) = (accounts.as_mut(), instance.results()) {
+                    accounts.record(&results);
+                    if is_ranked {
+                        for (account, change) in accounts.rate(&results) {
+                            info!("{} is now rated {:.0}", account, change.rating);
+                            if let Some(endpoint) = instance.endpoint_of(&account) {
+                                send_ranking(
+                                    &self.sender,
+                                    endpoint,
+                                    RankingMessage::Updated(change),
+                                );
+                            }
+                        }
+                    }
+                }
+                let in_tournament = match tournament.as_mut() {
+                    Some(tournament) => {
+                        let players = instance.players();
+                        let connected = players
+                            .iter()
+                            .map(|player| player.name.clone())
+                            .collect::<Vec<_>>();
+                        let played = tournament.finish(*id, instance.results(), &connected);
+                        if played {
+                            returning.extend(players);
+                        }
+                        played
+                    }
+                    None => false,
+                };
+                // Ranked players go back to the lobby to queue again instead,
+                // so ratings decide who they battle
+                if is_ranked {
+                    returning.extend(instance.players());
+                }
+                if let Some(rematch) = instance.rematch().filter(|_| !is_ranked && !in_tournament) {
+                    for endpoint in rematch.endpoints() {
+                        send_rematch(
+                            &self.sender,
+                            *endpoint,
+                            RematchMessage::Offer(REMATCH_TIME.as_secs_f32()),
+                        );
+                    }
+                    self.rematches.push(rematch);
+                }
+            }
+        }
+
+        self.battles.retain(|_, instance| !instance.finished());
+
+        for player in returning {
+            self.waiting
+                .insert(player.endpoint, WaitingPlayer::returning(player));
+        }
+
+        let mut index = 0;
+        while index < self.rematches.len() {
+            let rematch = &self.rematches[index];
+            if rematch.cancelled() {
+                let rematch = self.rematches.remove(index);
+                for endpoint in rematch.endpoints() {
+                    send_rematch(&self.sender, *endpoint, RematchMessage::Cancelled);
+                }
+            } else if rematch.agreed() {
+                let rematch = self.rematches.remove(index);
+                self.start_rematch(rematch);
+            } else {
+                index += 1;
+            }
+        }
+
+        self.update_tournament();
+    }
+
+    /// Whether there are no battles running.
+    pub fn idle(&self) -> bool {
+        self.battles.is_empty()
+    }
+
+    /// Replace the dexes and move engine used for new battles.
+    pub fn reload(
+        &mut self,
+        pokedex: &'d BasicDex<Pokemon>,
+        movedex: &'d BasicDex<Move>,
+        itemdex: &'d BasicDex<Item>,
+        engine: DefaultMoveEngine,
+        bundles: BundleHash,
+    ) {
+        self.pokedex = pokedex;
+        self.movedex = movedex;
+        self.itemdex = itemdex;
+        self.engine = engine;
+        self.bundles = bundles;
+    }
+
+    /// End all running battles.
+    pub fn end(&mut self) {
+        for (id, instance) in self.battles.iter_mut() {
+            instance.end(&self.sender);
+            if let (Some(directory), Some(replay)) = (
+                self.configuration.replays.as_deref(),
+                instance.take_replay(),
+            ) {
+                save_replay(directory, *id, &replay);
+            }
+        }
+        self.battles.clear();
+        self.routes.clear();
+        self.spectating.clear();
+    }
+
+    /// Handle a request to find or create a room.
+    fn lobby(&mut self, endpoint: Endpoint, request: LobbyRequest) {
+        if self.tournament.is_some() && !matches!(request, LobbyRequest::Rooms) {
+            return self.send_lobby(endpoint, LobbyMessage::Error(LobbyError::Tournament));
+        }
+        let (name, account) = match self.waiting.get(&endpoint) {
+            Some(waiting) if waiting.ready() => (
+                waiting.player.as_ref().map(|player| player.name.clone()),
+                waiting.account.clone(),
+            ),
+            _ => (None, None),
+        };
+        let result = match request {
+            LobbyRequest::Rooms => {
+                let rooms = self.lobby.rooms();
+                self.send_lobby(endpoint, LobbyMessage::Rooms(rooms));
+                Ok(())
+            }
+            LobbyRequest::Create(settings) => match name {
+                Some(name) => {
+                    self.dequeue(&endpoint);
+                    self.leave_room(&endpoint);
+                    let capacity = self.humans(settings.format) as _;
+                    self.lobby
+                        .create(endpoint, name, settings, capacity)
+                        .map(|room| {
+                            info!("Player at {} created room {}", endpoint, room);
+                            self.broadcast_room(room);
+                        })
+                }
+                None => Err(LobbyError::NotJoined),
+            },
+            LobbyRequest::Join(room, password) => match name {
+                Some(name) => match self.lobby.room_of(&endpoint) == Some(room) {
+                    true => Ok(()),
+                    false => {
+                        self.dequeue(&endpoint);
+                        self.leave_room(&endpoint);
+                        self.lobby
+                            .join(room, endpoint, name, password)
+                            .map(|()| self.broadcast_room(room))
+                    }
+                },
+                None => Err(LobbyError::NotJoined),
+            },
+            LobbyRequest::Leave => match self.leave_room(&endpoint) {
+                true => {
+                    self.send_lobby(endpoint, LobbyMessage::Left);
+                    Ok(())
+                }
+                false => Err(LobbyError::NotInRoom),
+            },
+            LobbyRequest::Ready(ready) => self
+                .lobby
+                .ready(&endpoint, ready)
+                .map(|room| self.broadcast_room(room)),
+            LobbyRequest::Kick(index) => self.lobby.kick(&endpoint, index).map(|(room, kicked)| {
+                info!("Player at {} was kicked from room {}", kicked, room);
+                self.send_lobby(kicked, LobbyMessage::Kicked);
+                self.broadcast_room(room);
+            }),
+            LobbyRequest::QuickMatch => match name {
+                Some(..) => {
+                    self.leave_room(&endpoint);
+                    self.ranked_queue.remove(&endpoint);
+                    if !self.queue.iter().any(|(e, ..)| e == &endpoint) {
+                        self.queue.push_back((endpoint, Instant::now()));
+                    }
+                    self.send_lobby(endpoint, LobbyMessage::Queued);
+                    Ok(())
+                }
+                None => Err(LobbyError::NotJoined),
+            },
+            LobbyRequest::RankedMatch => match name {
+                // Ratings only compare two players
+                Some(..) if self.format.players() != 2 => Err(LobbyError::NotRanked),
+                Some(..) => {
+                    // Guests are not rated, even with the name of an account
+                    let rating = account.and_then(|account| {
+                        self.accounts
+                            .as_ref()
+                            .and_then(|accounts| accounts.rating(&account))
+                            .map(|rating| (account, rating.rating))
+                    });
+                    match rating {
+                        Some((account, rating)) => {
+                            self.leave_room(&endpoint);
+                            self.queue.retain(|(e, ..)| e != &endpoint);
+                            if self.ranked_queue.push(endpoint, account, rating) {
+                                info!("Player at {} is waiting for a ranked battle", endpoint);
+                            }
+                            self.send_lobby(endpoint, LobbyMessage::Queued);
+                            Ok(())
+                        }
+                        None => Err(LobbyError::NoAccount),
+                    }
+                }
+                None => Err(LobbyError::NotJoined),
+            },
+        };
+        if let Err(err) = result {
+            self.send_lobby(endpoint, LobbyMessage::Error(err));
+        }
+    }
+
+    /// Start the tournament's matches once their players are ready,
+    /// and send everyone connected the bracket when it changes.
+    fn update_tournament(&mut self) {
+        let ready = self
+            .waiting
+            .iter()
+            .filter(|(.., waiting)| waiting.ready())
+            .flat_map(|(endpoint, waiting)| {
+                waiting
+                    .player
+                    .as_ref()
+                    .map(|player| (player.name.clone(), *endpoint))
+            })
+            .collect::<HashMap<_, _>>();
+        let timeout = Duration::from_secs(self.configuration.timeout);
+        let starts = match self.tournament.as_mut() {
+            Some(tournament) => tournament.update(&ready, timeout),
+            None => return,
+        };
+        for (index, endpoints) in starts {
+            let id = self.start(endpoints, self.format);
+            if let Some(tournament) = self.tournament.as_mut() {
+                tournament.started(index, id);
+            }
+        }
+        if let Some(bracket) = self.tournament.as_mut().and_then(Tournament::take_changed) {
+            let bytes = serialize(&NetServerMessage::<Id>::Tournament(bracket));
+            for endpoint in self.last_seen.keys() {
+                self.sender.send(*endpoint, bytes.clone());
+            }
+        }
+    }
+
+    /// Pass a chat message on to everyone in the sender's battle, or their room if they are not battling.
+    fn chat(&mut self, endpoint: Endpoint, text: &str) {
+        let battle = self
+            .routes
+            .get(&endpoint)
+            .and_then(|id| self.battles.get(id))
+            .and_then(|instance| instance.name(&endpoint).map(|name| (instance, name)));
+        let (scope, from) = match battle {
+            Some((.., name)) => (ChatScope::Battle, name.to_owned()),
+            None => match (
+                self.lobby.room_of(&endpoint),
+                self.waiting
+                    .get(&endpoint)
+                    .and_then(|waiting| waiting.player.as_ref()),
+            ) {
+                (Some(..), Some(player)) => (ChatScope::Room, player.name.clone()),
+                _ => return self.reject_chat(endpoint, ChatError::NoChannel),
+            },
+        };
+        let text = match self.chat.check(endpoint, text) {
+            Ok(text) => text,
+            Err(err) => return self.reject_chat(endpoint, err),
+        };
+        let message = NetServerMessage::<Id>::Chat(ChatMessage { scope, from, text });
+        match scope {
+            ChatScope::Battle => {
+                if let Some(instance) = self
+                    .routes
+                    .get(&endpoint)
+                    .and_then(|id| self.battles.get(id))
+                {
+                    instance.chat(&message, &self.sender);
+                }
+            }
+            ChatScope::Room => {
+                if let Some(room) = self.lobby.room_of(&endpoint) {
+                    let bytes = serialize(&message);
+                    for member in self.lobby.members(room) {
+                        self.sender.send(member, bytes.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    fn reject_chat(&self, endpoint: Endpoint, err: ChatError) {
+        self.sender.send(
+            endpoint,
+            serialize(&NetServerMessage::<Id>::ChatRejected(err)),
+        );
+    }
+
+    /// Put the players of a rematch back in their seats and start the battle.
+    fn start_rematch(&mut self, rematch: Rematch) {
+        let format = rematch.format;
+        let mut endpoints = Vec::new();
+        for RematchPlayer { mut player, vote } in rematch.into_players() {
+            let same_team = vote.map(|vote| vote.same_team).unwrap_or(true);
+            if !same_team && self.configuration.random_teams {
+                player.party = generate_party(&mut self.parties, self.pokedex.len() as _);
+            }
+            send_rematch(
+                &self.sender,
+                player.endpoint,
+                RematchMessage::Starting(match same_team {
+                    true => None,
+                    false => Some(player.party.clone()),
+                }),
+            );
+            endpoints.push(player.endpoint);
+            self.waiting
+                .insert(player.endpoint, WaitingPlayer::returning(player));
+        }
+        info!("Starting a rematch for the players at {:?}.", endpoints);
+        self.start(endpoints, format);
+    }
+
+    /// Count a player that leaves as declining their rematch.
+    fn decline_rematch(&mut self, endpoint: &Endpoint) {
+        if let Some(rematch) = self
+            .rematches
+            .iter_mut()
+            .find(|rematch| rematch.contains(endpoint))
+        {
+            rematch.vote(
+                endpoint,
+                RematchVote {
+                    accept: false,
+                    same_team: true,
+                },
+            );
+        }
+    }
+
+    /// Take an endpoint out of its room, returning whether it was in one.
+    fn leave_room(&mut self, endpoint: &Endpoint) -> bool {
+        let in_room = self.lobby.room_of(endpoint).is_some();
+        if let Some(room) = self.lobby.leave(endpoint) {
+            self.broadcast_room(room);
+        }
+        in_room
+    }
+
+    /// Send everyone in a room its new state.
+    fn broadcast_room(&self, room: RoomId) {
+        for (endpoint, state) in self.lobby.states(room) {
+            self.send_lobby(endpoint, LobbyMessage::Room(state));
+        }
+    }
+
+    fn send_lobby(&self, endpoint: Endpoint, message: LobbyMessage) {
+        self.sender
+            .send(endpoint, serialize(&NetServerMessage::<Id>::Lobby(message)));
+    }
+
+    /// Take an endpoint out of the quick and ranked queues.
+    fn dequeue(&mut self, endpoint: &Endpoint) {
+        self.queue.retain(|(e, ..)| e != endpoint);
+        self.ranked_queue.remove(endpoint);
+    }
+
+    /// Forget a player that has not started battling yet.
+    fn remove_waiting(&mut self, endpoint: &Endpoint) {
+        if self.waiting.remove(endpoint).is_some() {
+            self.dequeue(endpoint);
+            self.leave_room(endpoint);
+        }
+    }
+
+    fn stop_spectating(&mut self, endpoint: &Endpoint) {
+        if let Some(id) = self.spectating.remove(endpoint) {
+            if let Some(instance) = self.battles.get_mut(&id) {
+                instance.remove_spectator(endpoint);
+            }
+        }
+    }
+
+    /// Disconnect endpoints that have not been heard from within the configured timeout.
+    fn disconnect_silent(&mut self) {
+        let timeout = Duration::from_secs(self.configuration.timeout);
+
+        let silent = self
+            .last_seen
+            .iter()
+            .filter(|(.., seen)| seen.elapsed() >= timeout)
+            .map(|(endpoint, ..)| *endpoint)
+            .collect::<Vec<_>>();
+
+        for endpoint in silent {
+            info!(
+                "Endpoint at {} has not responded for {} seconds, disconnecting.",
+                endpoint,
+                timeout.as_secs()
+            );
+            self.last_seen.remove(&endpoint);
+            self.sender.disconnect(&endpoint);
+            self.chat.remove(&endpoint);
+            self.decline_rematch(&endpoint);
+            self.remove_waiting(&endpoint);
+            self.stop_spectating(&endpoint);
+            if let Some(id) = self.routes.remove(&endpoint) {
+                if let Some(instance) = self.battles.get_mut(&id) {
+                    instance.disconnect(&endpoint);
+                }
+            }
+        }
+    }
+
+    /// Number of human players needed to start a battle in the format.
+    fn humans(&self, format: BattleFormat) -> usize {
+        format
+            .players()
+            .saturating_sub(self.configuration.ai as usize)
+            .max(1)
+    }
+
+    /// Start a battle with the given endpoints, filling the remaining slots with AI players.
+    fn start(&mut self, endpoints: Vec<Endpoint>, format: BattleFormat) -> BattleId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let seed = self.configuration.seed.unwrap_or_else(|| self.random.gen());
+        let mut random = StdRng::seed_from_u64(seed);
+
+        let mut seats = Vec::with_capacity(endpoints.len());
+
+        let mut players = Vec::with_capacity(format.players());
+
+        for endpoint in endpoints {
+            let index = players.len();
+            let (token, player, account, party) = match self.waiting.remove(&endpoint) {
+                Some(WaitingPlayer {
+                    token,
+                    player,
+                    account,
+                    party: Some(party),
+                }) => (token, player, account, party),
+                _ => continue,
+            };
+            let clock = SeatClock::new(
+                self.configuration.turn_time,
+                self.configuration.total_time,
+                self.configuration.timeout_action,
+            );
+            let shadow = match clock.enabled() {
+                true => Some(ai_player(&mut random)),
+                false => None,
+            };
+            let name = player.map(|player| player.name);
+            let (seat, endpoint_player) = Seat::new(
+                index,
+                SeatPlayer {
+                    endpoint,
+                    token,
+                    name: name.clone().unwrap_or_default(),
+                    account,
+                    party: party.clone(),
+                },
+                clock,
+                shadow,
+            );
+            seats.push(seat);
+            self.routes.insert(endpoint, id);
+            players.push(PlayerData {
+                id: index as _,
+                name,
+                party,
+                settings: Default::default(),
+                endpoint: endpoint_player,
+            });
+        }
+
+        let mut observed = Vec::new();
+
+        for index in players.len()..format.players() {
+            let (observer, receiver) = crossbeam_channel::unbounded();
+            observed.push(receiver);
+            players.push(PlayerData {
+                id: index as _,
+                name: Some(format!("AI {}", index - seats.len() + 1)),
+                party: generate_party(&mut random, self.pokedex.len() as _),
+                settings: Default::default(),
+                endpoint: ObservedEndpoint::new(ai_player(&mut random), observer),
+            });
+        }
+
+        info!("Starting {} battle {} with seed {}.", format, id, seed);
+
+        let names = players.iter().map(|player| player.name.clone()).collect();
+
+        let replay = self.configuration.replays.as_ref().map(|_| {
+            let mut replay = Replay::new(Some(seed), self.bundles.dex);
+            replay.players = players
+                .iter()
+                .map(|player| ReplayPlayer {
+                    name: player.name.clone(),
+                    party: player.party.clone(),
+                    messages: Default::default(),
+                })
+                .collect();
+            replay
+        });
+
+        let battle = Battle::new(
+            BattleData {
+                type_: BattleType::Trainer,
+            },
+            &mut random,
+            format.active(),
+            self.pokedex,
+            self.movedex,
+            self.itemdex,
+            players.into_iter(),
+        );
+
+        let mut instance = BattleInstance::new(battle, format, random, seats, observed, replay);
+        instance.begin(names, &self.sender);
+        self.battles.insert(id, instance);
+
+        id
+    }
+
+    fn validate(&self, endpoint: Endpoint, message: ConnectMessage) {
+        self.sender.send(
+            endpoint,
+            serialize(&NetServerMessage::<Id>::Validate(message)),
+        );
+    }
+}
+
+fn send_rematch(sender: &PacketSender, endpoint: Endpoint, message: RematchMessage) {
+    sender.send(
+        endpoint,
+        serialize(&NetServerMessage::<Id>::Rematch(message)),
+    );
+}
+
+fn send_ranking(sender: &PacketSender, endpoint: Endpoint, message: RankingMessage) {
+    sender.send(
+        endpoint,
+        serialize(&NetServerMessage::<Id>::Ranking(message)),
+    );
+}
+
+fn save_replay(directory: &Path, id: BattleId, replay: &Replay<Id>) {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default();
+    let path = directory.join(format!("battle-{}-{}.{}", time, id, REPLAY_EXTENSION));
+    let bytes = match replay.to_bytes() {
+        Ok(bytes) => bytes,
+        Err(err) => return error!("Could not serialize replay with error {}", err),
+    };
+    match create_dir_all(directory).and_then(|()| write(&path, bytes)) {
+        Ok(()) => info!("Saved replay of battle {} to {:?}", id, path),
+        Err(err) => error!("Could not write replay to {:?} with error {}", path, err),
+    }
+}
@@ -1,5 +1,17 @@
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    rc::Rc,
+};
+
+use common::{
+    deserialize,
+    reliable::{Frame, ReliableChannel},
+    Id, NetClientMessage,
+};
 
+use crate::configuration::ListenAddresses;
 use naia_server_socket::{
     Packet, PacketReceiver as NaiaPacketReceiver, PacketSender as NaiaPacketSender, ServerAddrs,
     Socket as NaiaSocket,
@@ -7,68 +19,178 @@ use naia_server_socket::{
 
 pub type Endpoint = SocketAddr;
 
-pub struct Socket(NaiaSocket);
+/// Seconds an endpoint can go without sending anything before its channel is dropped.
+const CHANNEL_TIMEOUT: f32 = 120.0;
 
-impl Socket {
-    pub fn new(port: u16) -> Self {
-        let local = IpAddr::V4(Ipv4Addr::LOCALHOST);
+/// Carries payloads between the server and its endpoints, reliably and in the order they were sent.
+pub trait Transport {
+    fn send(&self, endpoint: Endpoint, bytes: Vec<u8>);
 
-        let address = SocketAddr::new(local, port);
+    /// Receive the next payload, in the order it was sent by its endpoint.
+    fn receive(&self) -> Option<(Endpoint, Vec<u8>)>;
 
-        let webrtc = SocketAddr::new(local, port + 1);
+    /// Send frames that have not been acknowledged in time again.
+    fn update(&self, delta: f32);
 
-        let server_addresses = ServerAddrs::new(address, webrtc, webrtc);
+    /// Forget the connection state of an endpoint.
+    fn disconnect(&self, endpoint: &Endpoint);
+}
+
+/// Transport shared between the server loop and everything that sends packets.
+pub type PacketSender = Rc<dyn Transport>;
+
+/// Transport over naia sockets, for native and WebRTC clients.
+pub struct Socket {
+    // Keep the socket alive for as long as the server is
+    _socket: NaiaSocket,
+    sender: NaiaPacketSender,
+    receiver: RefCell<NaiaPacketReceiver>,
+    /// Reliable channels for every endpoint that has opened a connection.
+    channels: RefCell<HashMap<Endpoint, Channel>>,
+    received: RefCell<VecDeque<(Endpoint, Vec<u8>)>>,
+}
+
+struct Channel {
+    channel: ReliableChannel,
+    /// Seconds since the endpoint last sent a frame.
+    silence: f32,
+}
+
+impl Socket {
+    pub fn new(addresses: &ListenAddresses) -> Self {
+        let server_addresses =
+            ServerAddrs::new(addresses.session, addresses.webrtc, addresses.public);
 
         let mut socket = NaiaSocket::new(Default::default()); // SocketConfig::new(LinkConditionerConfig::))
 
         socket.listen(server_addresses);
 
-        Socket(socket)
-    }
-
-    pub fn sender(&self) -> PacketSender {
-        self.0.get_packet_sender().into()
+        Self {
+            sender: socket.get_packet_sender(),
+            receiver: RefCell::new(socket.get_packet_receiver()),
+            _socket: socket,
+            channels: Default::default(),
+            received: Default::default(),
+        }
     }
 
-    pub fn receiver(&self) -> PacketReceiver {
-        self.0.get_packet_receiver().into()
+    fn send_raw(&self, endpoint: Endpoint, frame: Vec<u8>) {
+        self.sender.send(Packet::new(endpoint, frame))
     }
 }
 
-#[derive(Clone)]
-pub struct PacketSender(NaiaPacketSender);
-
-impl PacketSender {
-    pub fn send(&self, endpoint: Endpoint, bytes: Vec<u8>) {
-        self.0.send(Packet::new(endpoint, bytes))
+impl Transport for Socket {
+    fn send(&self, endpoint: Endpoint, bytes: Vec<u8>) {
+        let frame = match self.channels.borrow_mut().get_mut(&endpoint) {
+            Some(channel) => channel.channel.send(bytes),
+            None => return log::debug!("Not sending to {} as it has no connection", endpoint),
+        };
+        match frame {
+            Ok(frame) => self.send_raw(endpoint, frame),
+            Err(err) => log::error!("Could not frame packet with error {}", err),
+        }
     }
-}
 
-impl From<NaiaPacketSender> for PacketSender {
-    fn from(s: NaiaPacketSender) -> Self {
-        Self(s)
+    fn receive(&self) -> Option<(Endpoint, Vec<u8>)> {
+        let mut queue = self.received.borrow_mut();
+        while queue.is_empty() {
+            let packet = match self.receiver.borrow_mut().receive() {
+                Ok(packet) => packet?,
+                Err(err) => {
+                    log::error!("Cannot receive packets with error {}", err);
+                    return None;
+                }
+            };
+            let endpoint = packet.address();
+            let mut channels = self.channels.borrow_mut();
+            if !channels.contains_key(&endpoint) {
+                if !opens_connection(packet.payload()) {
+                    log::debug!("Ignoring packet from {} without a connection", endpoint);
+                    continue;
+                }
+                channels.insert(
+                    endpoint,
+                    Channel {
+                        channel: ReliableChannel::new(rand::random()),
+                        silence: 0.0,
+                    },
+                );
+            }
+            let received = match channels.get_mut(&endpoint) {
+                Some(channel) => {
+                    channel.silence = 0.0;
+                    channel.channel.receive(packet.payload())
+                }
+                None => continue,
+            };
+            drop(channels);
+            match received {
+                Ok(received) => {
+                    if let Some(ack) = received.ack {
+                        self.send_raw(endpoint, ack);
+                    }
+                    queue.extend(
+                        received
+                            .payloads
+                            .into_iter()
+                            .map(|payload| (endpoint, payload)),
+                    );
+                }
+                Err(err) => log::warn!("Could not read frame from {} with error {}", endpoint, err),
+            }
+        }
+        queue.pop_front()
     }
-}
-
-pub struct PacketReceiver(NaiaPacketReceiver);
 
-impl PacketReceiver {
-    pub fn receive(&mut self) -> Option<Packet> {
-        match self.0.receive() {
-            Ok(packet) => packet,
-            Err(err) => {
-                log::error!("Cannot receive packets with error {}", err);
-                None
+    fn update(&self, delta: f32) {
+        let mut channels = self.channels.borrow_mut();
+        channels.retain(|endpoint, channel| {
+            channel.silence += delta;
+            let expired = channel.channel.failed() || channel.silence >= CHANNEL_TIMEOUT;
+            if expired {
+                log::info!(
+                    "Dropping connection to {} as it stopped responding",
+                    endpoint
+                );
             }
+            !expired
+        });
+        let resend = channels
+            .iter_mut()
+            .flat_map(|(endpoint, channel)| {
+                let endpoint = *endpoint;
+                channel
+                    .channel
+                    .update(delta)
+                    .into_iter()
+                    .map(move |frame| (endpoint, frame))
+            })
+            .collect::<Vec<_>>();
+        drop(channels);
+        for (endpoint, frame) in resend {
+            self.send_raw(endpoint, frame);
         }
     }
-}
 
-impl From<NaiaPacketReceiver> for PacketReceiver {
-    fn from(r: NaiaPacketReceiver) -> Self {
-        Self(r)
+    fn disconnect(&self, endpoint: &Endpoint) {
+        self.channels.borrow_mut().remove(endpoint);
     }
 }
-// pub fn listen(port: u16) -> (PacketSender, PacketReceiver) {
 
-// }
+/// Whether a frame is the first one a client sends to join, rejoin or spectate,
+/// so that stray packets do not open connections.
+fn opens_connection(frame: &[u8]) -> bool {
+    match deserialize::<Frame>(frame) {
+        Ok(Frame::Data {
+            sequence: 0,
+            payload,
+            ..
+        }) => matches!(
+            deserialize::<NetClientMessage<Id>>(&payload),
+            Ok(NetClientMessage::RequestJoin(..)
+                | NetClientMessage::Rejoin(..)
+                | NetClientMessage::Spectate(..))
+        ),
+        _ => false,
+    }
+}
@@ -4,46 +4,39 @@ use std::fmt::Debug;
 
 use common::{
     battle::{
+        ai::BattlePlayerAi,
         endpoint::{BattleEndpoint, ReceiveError},
         message::{ClientMessage, ServerMessage},
     },
-    NetServerMessage,
+    Id,
 };
 
 use firecore_battle_net::pokedex::pokemon::{owned::SavedPokemon, party::Party, stat::StatSet};
-use rand::Rng;
+use rand::{prelude::StdRng, Rng, SeedableRng};
 use serde::Serialize;
 
-use crate::net::*;
-
-use crossbeam_channel::{Receiver, TryRecvError};
+use crossbeam_channel::{Receiver, Sender, TryRecvError};
 
+/// A battle endpoint that passes messages through channels to its seat in a battle.
 pub struct BattleServerPlayer<ID: Serialize + Debug> {
-    endpoint: Endpoint,
-    sender: PacketSender,
+    sender: Sender<ServerMessage<ID>>,
     receiver: Receiver<ClientMessage<ID>>,
 }
 
 impl<ID: Serialize + Debug> BattleServerPlayer<ID> {
     pub fn new(
-        endpoint: Endpoint,
-        sender: &PacketSender,
+        sender: Sender<ServerMessage<ID>>,
         receiver: Receiver<ClientMessage<ID>>,
     ) -> Box<Self> {
-        Box::new(Self {
-            endpoint,
-            sender: sender.clone(),
-            receiver,
-        })
+        Box::new(Self { sender, receiver })
     }
 }
 
 impl<ID: Serialize + Debug> BattleEndpoint<ID> for BattleServerPlayer<ID> {
     fn send(&mut self, message: ServerMessage<ID>) {
-        self.sender.send(
-            self.endpoint,
-            crate::serialize(&NetServerMessage::Game(message)),
-        );
+        if let Err(err) = self.sender.try_send(message) {
+            log::error!("Could not send over channel with error {}", err);
+        }
     }
 
     fn receive(&mut self) -> Result<ClientMessage<ID>, Option<ReceiveError>> {
@@ -54,6 +47,38 @@ impl<ID: Serialize + Debug> BattleEndpoint<ID> for BattleServerPlayer<ID> {
     }
 }
 
+/// An endpoint that passes on a copy of every message it is sent, so others can follow along.
+pub struct ObservedEndpoint<ID> {
+    endpoint: Box<dyn BattleEndpoint<ID>>,
+    observer: Sender<ServerMessage<ID>>,
+}
+
+impl<ID> ObservedEndpoint<ID> {
+    pub fn new(
+        endpoint: Box<dyn BattleEndpoint<ID>>,
+        observer: Sender<ServerMessage<ID>>,
+    ) -> Box<Self> {
+        Box::new(Self { endpoint, observer })
+    }
+}
+
+impl<ID: Clone> BattleEndpoint<ID> for ObservedEndpoint<ID> {
+    fn send(&mut self, message: ServerMessage<ID>) {
+        // The observer is gone once the battle is, which is fine to ignore
+        let _ = self.observer.try_send(message.clone());
+        self.endpoint.send(message);
+    }
+
+    fn receive(&mut self) -> Result<ClientMessage<ID>, Option<ReceiveError>> {
+        self.endpoint.receive()
+    }
+}
+
+/// Create an AI controlled endpoint with its own random number generator.
+pub fn ai_player(random: &mut impl Rng) -> Box<dyn BattleEndpoint<Id>> {
+    Box::new(BattlePlayerAi::new(StdRng::seed_from_u64(random.gen())))
+}
+
 pub fn generate_party(random: &mut impl Rng, pokedex_len: u16) -> Party<SavedPokemon> {
     let mut party = Party::new();
 
@@ -69,4 +94,4 @@ pub fn generate_party(random: &mut impl Rng, pokedex_len: u16) -> Party<SavedPok
     }
 
     party
-}
\ No newline at end of file
+}
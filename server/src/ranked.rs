@@ -0,0 +1,69 @@
+use std::time::Instant;
+
+use crate::net::Endpoint;
+
+/// Widest rating difference between paired players when they start waiting.
+const INITIAL_WINDOW: f64 = 100.0;
+/// How much wider the rating difference can be for each second a player waits.
+const WINDOW_GROWTH: f64 = 10.0;
+
+/// Players waiting for a ranked battle, kept apart from quick matches.
+#[derive(Default)]
+pub struct RankedQueue {
+    players: Vec<Waiting>,
+}
+
+struct Waiting {
+    endpoint: Endpoint,
+    name: String,
+    rating: f64,
+    since: Instant,
+}
+
+impl RankedQueue {
+    /// Add a player, returning false if a player with the same account is already waiting.
+    pub fn push(&mut self, endpoint: Endpoint, name: String, rating: f64) -> bool {
+        if self
+            .players
+            .iter()
+            .any(|player| player.endpoint == endpoint || player.name == name)
+        {
+            return false;
+        }
+        self.players.push(Waiting {
+            endpoint,
+            name,
+            rating,
+            since: Instant::now(),
+        });
+        true
+    }
+
+    pub fn remove(&mut self, endpoint: &Endpoint) {
+        self.players.retain(|player| &player.endpoint != endpoint);
+    }
+
+    /// Take out pairs of players with close enough ratings to battle each other.
+    /// The longer players wait, the further apart their ratings can be.
+    pub fn take_pairs(&mut self) -> Vec<[Endpoint; 2]> {
+        self.players.sort_by(|a, b| a.rating.total_cmp(&b.rating));
+        let mut pairs = Vec::new();
+        let mut index = 0;
+        while index + 1 < self.players.len() {
+            let (a, b) = (&self.players[index], &self.players[index + 1]);
+            if b.rating - a.rating <= a.window().max(b.window()) {
+                pairs.push([a.endpoint, b.endpoint]);
+                self.players.drain(index..index + 2);
+            } else {
+                index += 1;
+            }
+        }
+        pairs
+    }
+}
+
+impl Waiting {
+    fn window(&self) -> f64 {
+        INITIAL_WINDOW + self.since.elapsed().as_secs_f64() * WINDOW_GROWTH
+    }
+}
@@ -0,0 +1,80 @@
+use std::time::{Duration, Instant};
+
+use common::{lobby::BattleFormat, RematchVote};
+
+use crate::{battle::SeatPlayer, net::Endpoint};
+
+/// Time players have to vote for a rematch.
+pub const REMATCH_TIME: Duration = Duration::from_secs(30);
+
+/// A rematch offered to the players of a finished battle.
+pub struct Rematch {
+    /// Format of the finished battle.
+    pub format: BattleFormat,
+    players: Vec<RematchPlayer>,
+    offered: Instant,
+}
+
+/// A player of the finished battle, with what they need to sit in the same seat again.
+pub struct RematchPlayer {
+    pub player: SeatPlayer,
+    pub vote: Option<RematchVote>,
+}
+
+impl Rematch {
+    pub fn new(format: BattleFormat, players: Vec<SeatPlayer>) -> Self {
+        Self {
+            format,
+            players: players
+                .into_iter()
+                .map(|player| RematchPlayer { player, vote: None })
+                .collect(),
+            offered: Instant::now(),
+        }
+    }
+
+    pub fn endpoints(&self) -> impl Iterator<Item = &Endpoint> + '_ {
+        self.players.iter().map(|player| &player.player.endpoint)
+    }
+
+    pub fn contains(&self, endpoint: &Endpoint) -> bool {
+        self.endpoints().any(|e| e == endpoint)
+    }
+
+    /// Record the player's vote, replacing any earlier one.
+    pub fn vote(&mut self, endpoint: &Endpoint, vote: RematchVote) {
+        if let Some(player) = self
+            .players
+            .iter_mut()
+            .find(|player| &player.player.endpoint == endpoint)
+        {
+            player.vote = Some(vote);
+        }
+    }
+
+    pub fn votes(&self) -> Vec<Option<bool>> {
+        self.players
+            .iter()
+            .map(|player| player.vote.map(|vote| vote.accept))
+            .collect()
+    }
+
+    /// Whether the rematch will not happen, as someone declined or time ran out.
+    pub fn cancelled(&self) -> bool {
+        self.offered.elapsed() >= REMATCH_TIME
+            || self
+                .players
+                .iter()
+                .any(|player| matches!(player.vote, Some(vote) if !vote.accept))
+    }
+
+    pub fn agreed(&self) -> bool {
+        self.players
+            .iter()
+            .all(|player| matches!(player.vote, Some(vote) if vote.accept))
+    }
+
+    pub fn into_players(self) -> Vec<RematchPlayer> {
+        self.players
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+
+use log::{error, info};
+
+use common::{
+    deserialize,
+    pokedex::{item::Item, moves::Move, pokemon::Pokemon, BasicDex},
+    Id, NetClientMessage,
+};
+
+use crate::{
+    configuration::Configuration,
+    data::{Bundles, Dexes},
+    manager::BattleManager,
+    net::PacketSender,
+};
+
+type StaticDexes = (
+    &'static BasicDex<Pokemon>,
+    &'static BasicDex<Move>,
+    &'static BasicDex<Item>,
+);
+
+/// Passes what a transport receives to the battle manager and runs its battles.
+pub struct Server {
+    transport: PacketSender,
+    manager: BattleManager<'static>,
+    bundles: Bundles,
+    /// Dexes that have been loaded, by the hash of their bundle.
+    dexes: HashMap<u64, StaticDexes>,
+    /// Reload the bundles between battles when their files change.
+    reload: bool,
+}
+
+impl Server {
+    pub fn new(configuration: Configuration, transport: PacketSender) -> Result<Self, String> {
+        // Initialize pokemon
+
+        let mut bundles = Bundles::new(configuration.dex.clone(), configuration.battle.clone());
+
+        let mut dexes = HashMap::new();
+
+        let (pokedex, movedex, itemdex) = load_dexes(&mut bundles, &mut dexes);
+
+        let engine = bundles.engine();
+
+        let reload = configuration.reload;
+
+        let manager = BattleManager::new(
+            pokedex,
+            movedex,
+            itemdex,
+            engine,
+            bundles.hash,
+            transport.clone(),
+            configuration,
+        )?;
+
+        Ok(Self {
+            transport,
+            manager,
+            bundles,
+            dexes,
+            reload,
+        })
+    }
+
+    /// Handle incoming messages, run battles and resend anything that was not acknowledged.
+    pub fn update(&mut self, delta: f32) {
+        while let Some((endpoint, payload)) = self.transport.receive() {
+            match deserialize::<NetClientMessage<Id>>(&payload) {
+                Ok(message) => self.manager.receive(endpoint, message),
+                Err(err) => error!("Could not deserialize message with error {}", err),
+            }
+        }
+        self.manager.update();
+        if self.reload && self.manager.idle() && self.bundles.changed() {
+            info!("Bundles have changed, reloading.");
+            let (pokedex, movedex, itemdex) = load_dexes(&mut self.bundles, &mut self.dexes);
+            let engine = self.bundles.engine();
+            self.manager
+                .reload(pokedex, movedex, itemdex, engine, self.bundles.hash);
+        }
+        self.transport.update(delta);
+    }
+
+    /// End all running battles.
+    pub fn end(&mut self) {
+        self.manager.end();
+    }
+}
+
+/// Load the dexes, reusing ones already loaded from the same bundle.
+/// Battles borrow from the dexes, so they are kept until the server closes.
+fn load_dexes(bundles: &mut Bundles, loaded: &mut HashMap<u64, StaticDexes>) -> StaticDexes {
+    let dexes = bundles.dexes();
+    *loaded
+        .entry(bundles.hash.dex)
+        .or_insert_with(|| leak(dexes))
+}
+
+fn leak((pokedex, movedex, itemdex): Dexes) -> StaticDexes {
+    (
+        Box::leak(Box::new(pokedex)),
+        Box::leak(Box::new(movedex)),
+        Box::leak(Box::new(itemdex)),
+    )
+}
@@ -0,0 +1,42 @@
+use std::collections::HashSet;
+
+use common::{battle::message::ServerMessage, serialize, Id};
+
+/// Builds what spectators see of a battle from the messages sent to its players,
+/// passing on only what every player in the battle is shown.
+#[derive(Default)]
+pub struct SpectatorView {
+    /// Pokemon revealed so far, serialized, as each player is told about them separately.
+    revealed: HashSet<Vec<u8>>,
+}
+
+impl SpectatorView {
+    /// The message sent to a seat as spectators may see it, if they may see it at all.
+    /// Turns are taken from the first seat, as every player is sent the same ones.
+    /// No player's own party is passed on, only pokemon as they are revealed to the others.
+    pub fn public(
+        &mut self,
+        seat: usize,
+        message: &ServerMessage<Id>,
+    ) -> Option<ServerMessage<Id>> {
+        match message {
+            ServerMessage::Begin(data) if seat == 0 => {
+                let mut data = data.clone();
+                data.local.pokemon = Default::default();
+                Some(ServerMessage::Begin(data))
+            }
+            ServerMessage::TurnQueue(..)
+            | ServerMessage::FaintReplace(..)
+            | ServerMessage::GameEnd(..)
+                if seat == 0 =>
+            {
+                Some(message.clone())
+            }
+            ServerMessage::AddUnknown(..) => match serialize(message) {
+                Ok(bytes) if self.revealed.insert(bytes) => Some(message.clone()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
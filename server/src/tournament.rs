@@ -0,0 +1,412 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use log::info;
+
+use common::{
+    account::MatchResult,
+    tournament::{Bracket, BracketMatch, MatchOutcome, Standing, TournamentFormat},
+    BattleId,
+};
+
+use crate::{battle::SeatResult, configuration::TournamentSettings, net::Endpoint};
+
+/// Points for winning a Swiss match, including byes and opponents that did not show up.
+const WIN_POINTS: f32 = 1.0;
+const DRAW_POINTS: f32 = 0.5;
+
+/// A tournament between registered players, started round by round as its matches finish.
+pub struct Tournament {
+    format: TournamentFormat,
+    /// Registered players, best seed first.
+    players: Vec<Entrant>,
+    rounds: Vec<Vec<Match>>,
+    total_rounds: usize,
+    /// Players still in a single elimination bracket in seed order, with `None` for empty places.
+    alive: Vec<Option<usize>>,
+    created: Instant,
+    start_after: Duration,
+    finished: bool,
+    winner: Option<usize>,
+    /// Whether the bracket changed since it was last sent to clients.
+    changed: bool,
+}
+
+struct Entrant {
+    name: String,
+    points: f32,
+    eliminated: bool,
+    /// Whether the player has had a bye in a Swiss tournament.
+    bye: bool,
+    /// Players already battled in a Swiss tournament.
+    opponents: Vec<usize>,
+}
+
+struct Match {
+    players: Vec<usize>,
+    outcome: Option<Outcome>,
+    /// Battle the match is played in once it has started.
+    battle: Option<BattleId>,
+    /// When the match could first start, to count players that have not joined by the timeout as no-shows.
+    since: Instant,
+}
+
+#[derive(Clone, Copy)]
+enum Outcome {
+    Won(usize),
+    Drew,
+    NoShow(Option<usize>),
+    Bye,
+}
+
+impl Tournament {
+    pub fn new(settings: &TournamentSettings) -> Result<Self, String> {
+        let count = settings.players.len();
+        if count < 2 {
+            return Err("A tournament needs at least two players".to_owned());
+        }
+        for (index, name) in settings.players.iter().enumerate() {
+            if settings.players[..index].contains(name) {
+                return Err(format!(
+                    "Player {} is registered for the tournament twice",
+                    name
+                ));
+            }
+        }
+        let mut rounds = 0;
+        while 1 << rounds < count {
+            rounds += 1;
+        }
+        let total_rounds = match settings.format {
+            TournamentFormat::SingleElimination => rounds,
+            TournamentFormat::Swiss => settings.rounds.map(|rounds| rounds as _).unwrap_or(rounds),
+        };
+        info!(
+            "Running a {:?} tournament for {} players over {} rounds.",
+            settings.format, count, total_rounds
+        );
+        Ok(Self {
+            format: settings.format,
+            players: settings
+                .players
+                .iter()
+                .map(|name| Entrant {
+                    name: name.clone(),
+                    points: 0.0,
+                    eliminated: false,
+                    bye: false,
+                    opponents: Vec::new(),
+                })
+                .collect(),
+            rounds: Vec::new(),
+            total_rounds,
+            alive: (0..1 << rounds)
+                .map(|index| match index < count {
+                    true => Some(index),
+                    false => None,
+                })
+                .collect(),
+            created: Instant::now(),
+            start_after: Duration::from_secs(settings.start_after),
+            finished: false,
+            winner: None,
+            changed: true,
+        })
+    }
+
+    pub fn registered(&self, name: &str) -> bool {
+        self.players.iter().any(|player| player.name == name)
+    }
+
+    /// Begin the first round once everyone has joined or the start time has passed,
+    /// and give no-shows a loss once their match has waited for them longer than `timeout`.
+    /// Returns the matches of the current round to start, with the endpoints of their players.
+    pub fn update(
+        &mut self,
+        ready: &HashMap<String, Endpoint>,
+        timeout: Duration,
+    ) -> Vec<(usize, Vec<Endpoint>)> {
+        if self.finished {
+            return Vec::new();
+        }
+        if self.rounds.is_empty() {
+            let everyone = self
+                .players
+                .iter()
+                .all(|player| ready.contains_key(&player.name));
+            if !everyone && self.created.elapsed() < self.start_after {
+                return Vec::new();
+            }
+            self.next_round();
+        }
+
+        let mut starts = Vec::new();
+        let players = &self.players;
+        let mut no_shows = false;
+        if let Some(round) = self.rounds.last_mut() {
+            for (index, game) in round.iter_mut().enumerate() {
+                if game.outcome.is_some() || game.battle.is_some() {
+                    continue;
+                }
+                let present = game
+                    .players
+                    .iter()
+                    .flat_map(|player| ready.get(&players[*player].name).map(|e| (*player, *e)))
+                    .collect::<Vec<_>>();
+                if present.len() == game.players.len() {
+                    starts.push((index, present.iter().map(|(.., e)| *e).collect()));
+                } else if game.since.elapsed() >= timeout {
+                    let winner = match present.as_slice() {
+                        [(winner, ..)] => Some(*winner),
+                        _ => None,
+                    };
+                    game.outcome = Some(Outcome::NoShow(winner));
+                    no_shows = true;
+                }
+            }
+        }
+        if no_shows {
+            self.changed = true;
+            self.advance();
+        }
+        starts
+    }
+
+    /// Remember the battle a match of the current round is played in.
+    pub fn started(&mut self, index: usize, id: BattleId) {
+        if let Some(game) = self
+            .rounds
+            .last_mut()
+            .and_then(|round| round.get_mut(index))
+        {
+            game.battle = Some(id);
+        }
+    }
+
+    /// Record how a battle ended, returning whether it was a match of the tournament.
+    /// A battle without a winner goes to the only player still connected, if there is one.
+    pub fn finish(
+        &mut self,
+        id: BattleId,
+        results: Option<Vec<SeatResult>>,
+        connected: &[String],
+    ) -> bool {
+        let players = &self.players;
+        let game = match self
+            .rounds
+            .last_mut()
+            .and_then(|round| round.iter_mut().find(|game| game.battle == Some(id)))
+        {
+            Some(game) => game,
+            None => return false,
+        };
+        let winner = results
+            .iter()
+            .flatten()
+            .find(|seat| seat.result == MatchResult::Win)
+            .map(|seat| &seat.name);
+        let winner = winner.or_else(|| match connected {
+            [name] => Some(name),
+            _ => None,
+        });
+        let winner = winner.and_then(|name| {
+            game.players
+                .iter()
+                .copied()
+                .find(|player| &players[*player].name == name)
+        });
+        game.outcome = Some(match winner {
+            Some(winner) => Outcome::Won(winner),
+            None => Outcome::Drew,
+        });
+        self.changed = true;
+        self.advance();
+        true
+    }
+
+    /// The bracket to send to clients, if it changed since it was last taken.
+    pub fn take_changed(&mut self) -> Option<Bracket> {
+        match self.changed {
+            true => {
+                self.changed = false;
+                Some(self.bracket())
+            }
+            false => None,
+        }
+    }
+
+    pub fn bracket(&self) -> Bracket {
+        let name = |player: usize| self.players[player].name.clone();
+        let mut standings = (0..self.players.len()).collect::<Vec<_>>();
+        match self.format {
+            TournamentFormat::SingleElimination => {
+                standings.sort_by_key(|player| self.players[*player].eliminated)
+            }
+            TournamentFormat::Swiss => standings
+                .sort_by(|a, b| self.players[*b].points.total_cmp(&self.players[*a].points)),
+        }
+        Bracket {
+            format: self.format,
+            rounds: self
+                .rounds
+                .iter()
+                .map(|round| {
+                    round
+                        .iter()
+                        .map(|game| BracketMatch {
+                            players: game.players.iter().copied().map(name).collect(),
+                            outcome: game.outcome.map(|outcome| match outcome {
+                                Outcome::Won(winner) => MatchOutcome::Won(name(winner)),
+                                Outcome::Drew => MatchOutcome::Drew,
+                                Outcome::NoShow(winner) => MatchOutcome::NoShow(winner.map(name)),
+                                Outcome::Bye => MatchOutcome::Bye,
+                            }),
+                        })
+                        .collect()
+                })
+                .collect(),
+            total_rounds: self.total_rounds as _,
+            standings: standings
+                .into_iter()
+                .map(|player| Standing {
+                    name: name(player),
+                    points: self.players[player].points,
+                    eliminated: self.players[player].eliminated,
+                })
+                .collect(),
+            winner: self.winner.map(name),
+        }
+    }
+
+    /// Score the current round once all of its matches are over, then pair the next one or finish.
+    fn advance(&mut self) {
+        let round = match self.rounds.last() {
+            Some(round) if round.iter().all(|game| game.outcome.is_some()) => round,
+            _ => return,
+        };
+        let mut winners = HashMap::new();
+        for game in round {
+            let winner = match game.outcome {
+                Some(Outcome::Won(winner)) | Some(Outcome::NoShow(Some(winner))) => {
+                    self.players[winner].points += WIN_POINTS;
+                    Some(winner)
+                }
+                Some(Outcome::Bye) => {
+                    let player = game.players[0];
+                    self.players[player].points += WIN_POINTS;
+                    self.players[player].bye = true;
+                    Some(player)
+                }
+                Some(Outcome::Drew) => {
+                    for player in game.players.iter() {
+                        self.players[*player].points += DRAW_POINTS;
+                    }
+                    // The better seed goes through a drawn elimination match
+                    game.players.iter().copied().min()
+                }
+                Some(Outcome::NoShow(None)) | None => None,
+            };
+            for player in game.players.iter() {
+                winners.insert(*player, winner);
+            }
+        }
+
+        if self.format == TournamentFormat::SingleElimination {
+            let length = self.alive.len();
+            let alive = (0..length / 2)
+                .map(|index| {
+                    self.alive[index]
+                        .or(self.alive[length - 1 - index])
+                        .and_then(|player| winners.get(&player).copied().flatten())
+                })
+                .collect::<Vec<_>>();
+            for (player, entrant) in self.players.iter_mut().enumerate() {
+                entrant.eliminated = !alive.contains(&Some(player));
+            }
+            self.alive = alive;
+        }
+
+        match self.rounds.len() >= self.total_rounds {
+            true => self.end(),
+            false => self.next_round(),
+        }
+    }
+
+    fn next_round(&mut self) {
+        let now = Instant::now();
+        let game = |players: Vec<usize>| Match {
+            outcome: match players.len() {
+                1 => Some(Outcome::Bye),
+                _ => None,
+            },
+            players,
+            battle: None,
+            since: now,
+        };
+        let round = match self.format {
+            TournamentFormat::SingleElimination => {
+                let length = self.alive.len();
+                (0..length / 2)
+                    .map(|index| {
+                        [self.alive[index], self.alive[length - 1 - index]]
+                            .iter()
+                            .flatten()
+                            .copied()
+                            .collect::<Vec<_>>()
+                    })
+                    .filter(|players| !players.is_empty())
+                    .map(game)
+                    .collect::<Vec<_>>()
+            }
+            TournamentFormat::Swiss => {
+                let mut order = (0..self.players.len()).collect::<Vec<_>>();
+                order.sort_by(|a, b| self.players[*b].points.total_cmp(&self.players[*a].points));
+                let mut round = Vec::new();
+                if order.len() % 2 == 1 {
+                    let bye = order
+                        .iter()
+                        .rposition(|player| !self.players[*player].bye)
+                        .unwrap_or(order.len() - 1);
+                    round.push(game(vec![order.remove(bye)]));
+                }
+                while !order.is_empty() {
+                    let player = order.remove(0);
+                    let opponent = order
+                        .iter()
+                        .position(|other| !self.players[player].opponents.contains(other))
+                        .unwrap_or(0);
+                    let opponent = order.remove(opponent);
+                    self.players[player].opponents.push(opponent);
+                    self.players[opponent].opponents.push(player);
+                    round.push(game(vec![player, opponent]));
+                }
+                round
+            }
+        };
+        self.rounds.push(round);
+        self.changed = true;
+        info!("Starting round {} of the tournament.", self.rounds.len());
+        self.advance();
+    }
+
+    fn end(&mut self) {
+        self.finished = true;
+        self.winner = match self.format {
+            TournamentFormat::SingleElimination => self.alive.iter().flatten().copied().next(),
+            TournamentFormat::Swiss => {
+                (0..self.players.len()).fold(None, |best, player| match best {
+                    Some(best) if self.players[best].points >= self.players[player].points => {
+                        Some(best)
+                    }
+                    _ => Some(player),
+                })
+            }
+        };
+        match self.winner {
+            Some(winner) => info!("{} won the tournament.", self.players[winner].name),
+            None => info!("The tournament ended without a winner."),
+        }
+    }
+}
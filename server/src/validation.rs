@@ -0,0 +1,136 @@
+use std::collections::HashSet;
+
+use common::pokedex::{
+    item::Item,
+    moves::Move,
+    pokemon::{
+        owned::SavedPokemon,
+        party::Party,
+        stat::{Stat, StatSet},
+        Pokemon,
+    },
+    BasicDex, Dex,
+};
+
+use crate::configuration::Rules;
+
+pub const MAX_LEVEL: u8 = 100;
+pub const MAX_IV: Stat = 31;
+pub const MAX_EV: Stat = 252;
+pub const MAX_EV_TOTAL: u16 = 510;
+
+/// Check a submitted party against the dexes and the server's rules.
+/// Returns a human-readable reason for every problem found, or nothing if the party is legal.
+pub fn validate_party(
+    rules: &Rules,
+    pokedex: &BasicDex<Pokemon>,
+    movedex: &BasicDex<Move>,
+    itemdex: &BasicDex<Item>,
+    party: &Party<SavedPokemon>,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if party.is_empty() {
+        problems.push("Party is empty".to_owned());
+    }
+
+    let mut species = HashSet::with_capacity(party.len());
+    let mut items = HashSet::with_capacity(party.len());
+
+    for (index, saved) in party.iter().enumerate() {
+        let slot = index + 1;
+
+        let pokemon = match pokedex.try_get(&saved.pokemon) {
+            Some(pokemon) => pokemon,
+            None => {
+                problems.push(format!("#{}: Unknown species {}", slot, saved.pokemon));
+                continue;
+            }
+        };
+
+        let name = &pokemon.name;
+
+        if rules.species_clause && !species.insert(pokemon.id) {
+            problems.push(format!(
+                "#{} {}: Species clause allows only one of each species",
+                slot, name
+            ));
+        }
+
+        if saved.level == 0 || saved.level > MAX_LEVEL {
+            problems.push(format!(
+                "#{} {}: Level {} is not between 1 and {}",
+                slot, name, saved.level, MAX_LEVEL
+            ));
+        } else if saved.level > rules.level_cap {
+            problems.push(format!(
+                "#{} {}: Level {} is above the level cap of {}",
+                slot, name, saved.level, rules.level_cap
+            ));
+        }
+
+        if stats(&saved.ivs).iter().any(|iv| *iv > MAX_IV) {
+            problems.push(format!(
+                "#{} {}: IVs must be at most {}",
+                slot, name, MAX_IV
+            ));
+        }
+
+        let evs = stats(&saved.evs);
+
+        if evs.iter().any(|ev| *ev > MAX_EV) {
+            problems.push(format!(
+                "#{} {}: EVs must be at most {} per stat",
+                slot, name, MAX_EV
+            ));
+        }
+
+        let total = evs.iter().map(|ev| *ev as u16).sum::<u16>();
+
+        if total > MAX_EV_TOTAL {
+            problems.push(format!(
+                "#{} {}: EVs total {} which is above {}",
+                slot, name, total, MAX_EV_TOTAL
+            ));
+        }
+
+        if saved.moves.is_empty() {
+            problems.push(format!("#{} {}: Has no moves", slot, name));
+        }
+
+        for m in saved.moves.iter() {
+            let id = &m.0;
+            match movedex.try_get(id) {
+                Some(m) => {
+                    if !pokemon.moves.iter().any(|learnable| &learnable.1 == id) {
+                        problems.push(format!(
+                            "#{} {}: Cannot learn the move {}",
+                            slot, name, m.name
+                        ));
+                    }
+                }
+                None => problems.push(format!("#{} {}: Unknown move {}", slot, name, id)),
+            }
+        }
+
+        if let Some(id) = &saved.item {
+            match itemdex.try_get(id) {
+                Some(item) => {
+                    if rules.item_clause && !items.insert(*id) {
+                        problems.push(format!(
+                            "#{} {}: Item clause allows only one {}",
+                            slot, name, item.name
+                        ));
+                    }
+                }
+                None => problems.push(format!("#{} {}: Unknown item {}", slot, name, id)),
+            }
+        }
+    }
+
+    problems
+}
+
+fn stats(set: &StatSet) -> [Stat; 6] {
+    [set.hp, set.atk, set.def, set.sp_atk, set.sp_def, set.speed]
+}
@@ -0,0 +1,615 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    rc::Rc,
+};
+
+use firecore_battle_net::{
+    battle::{
+        message::{ClientMessage, ServerMessage},
+        moves::BattleMove,
+    },
+    deserialize,
+    lobby::{BattleFormat, LobbyMessage, LobbyRequest},
+    ranking::{RankingMessage, RankingRequest, INITIAL_RATING},
+    serialize, BundleHash, ConnectMessage, Id, NetClientMessage, NetServerMessage, Player,
+    RematchMessage, RematchVote, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+
+use pokemon_battle_server::{
+    configuration::Configuration,
+    loopback::{Loopback, LoopbackClient},
+    Server,
+};
+
+/// Most updates a battle gets to react to a message before a test fails.
+const MAX_UPDATES: usize = 100;
+
+/// A scripted client that speaks in messages instead of bytes.
+struct Client(LoopbackClient);
+
+impl Client {
+    fn send(&self, message: NetClientMessage<Id>) {
+        self.0.send(serialize(&message).unwrap());
+    }
+
+    /// Every message the server has sent since the last call.
+    fn received(&self) -> Vec<NetServerMessage<Id>> {
+        std::iter::from_fn(|| self.0.receive())
+            .map(|bytes| deserialize(&bytes).unwrap())
+            .collect()
+    }
+}
+
+fn start() -> (Server, Loopback) {
+    start_with(Configuration {
+        seed: Some(0),
+        ..Default::default()
+    })
+}
+
+fn start_with(configuration: Configuration) -> (Server, Loopback) {
+    let loopback = Loopback::new();
+    let server = Server::new(configuration, Rc::new(loopback.clone())).unwrap();
+    (server, loopback)
+}
+
+fn connect(loopback: &Loopback, port: u16) -> Client {
+    Client(loopback.client(SocketAddr::from((Ipv4Addr::LOCALHOST, port))))
+}
+
+/// Join the server with the party it generates.
+fn join(server: &mut Server, client: &Client, name: &str) {
+    login(server, client, name, None)
+}
+
+/// Join the server with the party it generates, logging in to an account if a password is given.
+fn login(server: &mut Server, client: &Client, name: &str, password: Option<&str>) {
+    client.send(NetClientMessage::RequestJoin(
+        PROTOCOL_VERSION,
+        BundleHash::BUILT,
+    ));
+    server.update(0.0);
+    let received = client.received();
+    assert!(
+        matches!(
+            received.as_slice(),
+            [NetServerMessage::Validate(ConnectMessage::CanJoin(Some(
+                ..
+            )))]
+        ),
+        "{:?}",
+        received
+    );
+
+    client.send(NetClientMessage::Join(Player {
+        name: name.to_owned(),
+        password: password.map(str::to_owned),
+    }));
+    server.update(0.0);
+    let received = client.received();
+    assert!(
+        matches!(
+            received.as_slice(),
+            [NetServerMessage::Validate(ConnectMessage::Session(..))]
+        ),
+        "{:?}",
+        received
+    );
+}
+
+/// Join two players and put them in a quick match against each other.
+fn battle(server: &mut Server, red: &Client, blue: &Client) {
+    battle_in(server, red, blue, BattleFormat::Singles)
+}
+
+/// Join two players and put them in a quick match in the server's format.
+fn battle_in(server: &mut Server, red: &Client, blue: &Client, format: BattleFormat) {
+    join(server, red, "Red");
+    red.send(NetClientMessage::Lobby(LobbyRequest::QuickMatch));
+    server.update(0.0);
+    let received = red.received();
+    assert!(
+        matches!(
+            received.as_slice(),
+            [NetServerMessage::Lobby(LobbyMessage::Queued)]
+        ),
+        "{:?}",
+        received
+    );
+
+    join(server, blue, "Blue");
+    blue.send(NetClientMessage::Lobby(LobbyRequest::QuickMatch));
+    server.update(0.0);
+
+    let received = red.received();
+    assert_setup(&received, format, 0);
+
+    // Blue is queued and matched with Red in the same update
+    let received = blue.received();
+    assert!(
+        matches!(
+            received.first(),
+            Some(NetServerMessage::Lobby(LobbyMessage::Queued))
+        ),
+        "{:?}",
+        received
+    );
+    assert_setup(&received[1..], format, 1);
+}
+
+/// The battle starts with its format and players, followed only by game messages.
+fn assert_setup(received: &[NetServerMessage<Id>], format: BattleFormat, you: u8) {
+    match received.first() {
+        Some(NetServerMessage::Setup(setup)) => {
+            assert_eq!(setup.format, format);
+            assert_eq!(
+                setup.players,
+                vec![Some("Red".to_owned()), Some("Blue".to_owned())]
+            );
+            assert_eq!(setup.you, Some(you));
+        }
+        other => panic!("Expected battle setup, got {:?}", other),
+    }
+    assert!(
+        received[1..].iter().all(|message| matches!(
+            message,
+            NetServerMessage::Game(message) if !matches!(message, ServerMessage::GameEnd(..))
+        )),
+        "{:?}",
+        received
+    );
+}
+
+/// Update the server until every client's battle has ended, returning what each received after the game messages.
+fn finish(
+    server: &mut Server,
+    clients: &[&Client],
+    winner: Option<Id>,
+) -> Vec<Vec<NetServerMessage<Id>>> {
+    let mut received = clients.iter().map(|_| Vec::new()).collect::<Vec<_>>();
+    for _ in 0..MAX_UPDATES {
+        server.update(0.0);
+        for (client, received) in clients.iter().zip(received.iter_mut()) {
+            received.extend(client.received());
+        }
+        let ended = received.iter().all(|received| {
+            received.iter().any(|message| {
+                matches!(message, NetServerMessage::Game(ServerMessage::GameEnd(..)))
+            })
+        });
+        if ended {
+            break;
+        }
+    }
+    received
+        .into_iter()
+        .map(|received| {
+            let end = received
+                .iter()
+                .position(|message| {
+                    matches!(message, NetServerMessage::Game(ServerMessage::GameEnd(..)))
+                })
+                .unwrap_or_else(|| panic!("Battle did not end: {:?}", received));
+            assert!(
+                received[..end]
+                    .iter()
+                    .all(|message| matches!(message, NetServerMessage::Game(..))),
+                "{:?}",
+                received
+            );
+            match &received[end] {
+                NetServerMessage::Game(ServerMessage::GameEnd(ended)) => {
+                    assert_eq!(*ended, winner)
+                }
+                _ => unreachable!(),
+            }
+            received.into_iter().skip(end + 1).collect()
+        })
+        .collect()
+}
+
+#[test]
+fn ping() {
+    let (mut server, loopback) = start();
+    let client = connect(&loopback, 1);
+
+    client.send(NetClientMessage::Ping);
+    server.update(0.0);
+    let received = client.received();
+    assert!(
+        matches!(received.as_slice(), [NetServerMessage::Pong]),
+        "{:?}",
+        received
+    );
+}
+
+#[test]
+fn wrong_version() {
+    let (mut server, loopback) = start();
+    let client = connect(&loopback, 1);
+
+    client.send(NetClientMessage::RequestJoin(
+        MIN_PROTOCOL_VERSION - 1,
+        BundleHash::BUILT,
+    ));
+    server.update(0.0);
+    let received = client.received();
+    match received.as_slice() {
+        [NetServerMessage::Validate(ConnectMessage::WrongVersion(range))] => {
+            assert_eq!(range.min, MIN_PROTOCOL_VERSION);
+            assert_eq!(range.max, PROTOCOL_VERSION);
+        }
+        other => panic!("Expected wrong version, got {:?}", other),
+    }
+}
+
+#[test]
+fn oldest_compatible_version() {
+    let (mut server, loopback) = start();
+    let client = connect(&loopback, 1);
+
+    client.send(NetClientMessage::RequestJoin(
+        MIN_PROTOCOL_VERSION,
+        BundleHash::BUILT,
+    ));
+    server.update(0.0);
+    let received = client.received();
+    assert!(
+        matches!(
+            received.as_slice(),
+            [NetServerMessage::Validate(ConnectMessage::CanJoin(..))]
+        ),
+        "{:?}",
+        received
+    );
+}
+
+#[test]
+fn forfeit_and_decline_rematch() {
+    let (mut server, loopback) = start();
+    let red = connect(&loopback, 1);
+    let blue = connect(&loopback, 2);
+
+    battle(&mut server, &red, &blue);
+
+    red.send(NetClientMessage::Game(ClientMessage::Forfeit));
+    let received = finish(&mut server, &[&red, &blue], Some(1));
+    for received in received {
+        assert!(
+            matches!(
+                received.as_slice(),
+                [NetServerMessage::Rematch(RematchMessage::Offer(..))]
+            ),
+            "{:?}",
+            received
+        );
+    }
+
+    blue.send(NetClientMessage::Rematch(RematchVote {
+        accept: false,
+        same_team: true,
+    }));
+    server.update(0.0);
+    for client in [&red, &blue] {
+        let received = client.received();
+        match received.as_slice() {
+            [NetServerMessage::Rematch(RematchMessage::Votes(votes)), NetServerMessage::Rematch(RematchMessage::Cancelled)] =>
+            {
+                assert_eq!(votes, &vec![None, Some(false)])
+            }
+            other => panic!("Expected rematch to be cancelled, got {:?}", other),
+        }
+    }
+
+    red.send(NetClientMessage::Leave);
+    blue.send(NetClientMessage::Leave);
+    server.update(0.0);
+    assert!(red.received().is_empty());
+    assert!(blue.received().is_empty());
+}
+
+#[test]
+fn leaving_forfeits() {
+    let (mut server, loopback) = start();
+    let red = connect(&loopback, 1);
+    let blue = connect(&loopback, 2);
+
+    battle(&mut server, &red, &blue);
+
+    // Blue loses by leaving, and is gone so Red is not offered a rematch
+    blue.send(NetClientMessage::Leave);
+    let received = finish(&mut server, &[&red], Some(0));
+    assert!(received[0].is_empty(), "{:?}", received);
+    assert!(blue.received().is_empty());
+
+    // Red can join again for another battle
+    join(&mut server, &red, "Red");
+}
+
+#[test]
+fn leaving_ranked_battle_loses_rating() {
+    let path = std::env::temp_dir().join(format!(
+        "pokemon-battle-accounts-{}.toml",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    let (mut server, loopback) = start_with(Configuration {
+        seed: Some(0),
+        accounts: Some(path.clone()),
+        ..Default::default()
+    });
+    let red = connect(&loopback, 1);
+    let blue = connect(&loopback, 2);
+
+    login(&mut server, &red, "Red", Some("red"));
+    red.send(NetClientMessage::Lobby(LobbyRequest::RankedMatch));
+    server.update(0.0);
+    let received = red.received();
+    assert!(
+        matches!(
+            received.as_slice(),
+            [NetServerMessage::Lobby(LobbyMessage::Queued)]
+        ),
+        "{:?}",
+        received
+    );
+
+    login(&mut server, &blue, "Blue", Some("blue"));
+    blue.send(NetClientMessage::Lobby(LobbyRequest::RankedMatch));
+    server.update(0.0);
+    assert_setup(&red.received(), BattleFormat::Singles, 0);
+    assert_setup(&blue.received()[1..], BattleFormat::Singles, 1);
+
+    blue.send(NetClientMessage::Leave);
+    let received = finish(&mut server, &[&red], Some(0));
+    match received[0].as_slice() {
+        [NetServerMessage::Ranking(RankingMessage::Updated(change))] => {
+            assert!(change.change > 0.0, "{:?}", change)
+        }
+        other => panic!("Expected rating change, got {:?}", other),
+    }
+
+    red.send(NetClientMessage::Ranking(RankingRequest::History(
+        "Blue".to_owned(),
+    )));
+    server.update(0.0);
+    let received = red.received();
+    match received.as_slice() {
+        [NetServerMessage::Ranking(RankingMessage::History(name, Some(rating)))] => {
+            assert_eq!(name, "Blue");
+            assert!(rating.rating < INITIAL_RATING, "{:?}", rating);
+        }
+        other => panic!("Expected Blue's rating, got {:?}", other),
+    }
+
+    // Red is back in the lobby and can queue for another ranked battle
+    red.send(NetClientMessage::Lobby(LobbyRequest::RankedMatch));
+    server.update(0.0);
+    let received = red.received();
+    assert!(
+        matches!(
+            received.as_slice(),
+            [NetServerMessage::Lobby(LobbyMessage::Queued)]
+        ),
+        "{:?}",
+        received
+    );
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn guests_are_not_credited_to_accounts() {
+    let path =
+        std::env::temp_dir().join(format!("pokemon-battle-guests-{}.toml", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let (mut server, loopback) = start_with(Configuration {
+        seed: Some(0),
+        accounts: Some(path.clone()),
+        ..Default::default()
+    });
+    let red = connect(&loopback, 1);
+    let blue = connect(&loopback, 2);
+    let other = connect(&loopback, 3);
+
+    // Red plays as a guest, and someone else registers the name during the battle
+    battle(&mut server, &red, &blue);
+    login(&mut server, &other, "Red", Some("red"));
+
+    blue.send(NetClientMessage::Game(ClientMessage::Forfeit));
+    finish(&mut server, &[&red, &blue], Some(0));
+
+    other.send(NetClientMessage::Stats("Red".to_owned()));
+    server.update(0.0);
+    let received = other.received();
+    match received.as_slice() {
+        [NetServerMessage::Stats(name, Some(stats))] => {
+            assert_eq!(name, "Red");
+            assert_eq!((stats.wins, stats.losses, stats.draws), (0, 0, 0));
+            assert!(stats.history.is_empty(), "{:?}", stats);
+        }
+        other => panic!("Expected Red's stats, got {:?}", other),
+    }
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn answering_one_slot_keeps_the_clock_running() {
+    let (mut server, loopback) = start_with(Configuration {
+        seed: Some(0),
+        format: Some(BattleFormat::Doubles),
+        turn_time: Some(1),
+        ..Default::default()
+    });
+    let red = connect(&loopback, 1);
+    let blue = connect(&loopback, 2);
+
+    battle_in(&mut server, &red, &blue, BattleFormat::Doubles);
+
+    let mut received = Vec::new();
+    for _ in 0..MAX_UPDATES {
+        server.update(0.0);
+        received.extend(red.received());
+        blue.received();
+        if received.iter().any(|message| {
+            matches!(
+                message,
+                NetServerMessage::Game(ServerMessage::StartSelecting)
+            )
+        }) {
+            break;
+        }
+    }
+    assert!(
+        received
+            .iter()
+            .any(|message| matches!(message, NetServerMessage::Clock(clock) if clock.running)),
+        "{:?}",
+        received
+    );
+
+    // Blue answers for both of its pokemon, Red only for its first
+    for slot in 0..2 {
+        blue.send(NetClientMessage::Game(ClientMessage::Move(
+            slot,
+            BattleMove::Move(0, None),
+        )));
+    }
+    red.send(NetClientMessage::Game(ClientMessage::Move(
+        0,
+        BattleMove::Move(0, None),
+    )));
+    server.update(0.0);
+    let received = red.received();
+    assert!(
+        !received
+            .iter()
+            .any(|message| matches!(message, NetServerMessage::Clock(..))),
+        "{:?}",
+        received
+    );
+    let received = blue.received();
+    assert!(
+        received
+            .iter()
+            .any(|message| matches!(message, NetServerMessage::Clock(clock) if !clock.running)),
+        "{:?}",
+        received
+    );
+
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    server.update(0.0);
+    let received = red.received();
+    assert!(
+        received
+            .iter()
+            .any(|message| matches!(message, NetServerMessage::TimedOut)),
+        "{:?}",
+        received
+    );
+    assert!(!blue
+        .received()
+        .iter()
+        .any(|message| matches!(message, NetServerMessage::TimedOut)));
+}
+
+/// Play the first turn of a quick match with generated parties, each player using their first move.
+/// Returns everything each player was sent during the battle.
+fn first_turn(seed: u64) -> Vec<String> {
+    let (mut server, loopback) = start_with(Configuration {
+        seed: Some(seed),
+        ..Default::default()
+    });
+    let red = connect(&loopback, 1);
+    let blue = connect(&loopback, 2);
+
+    battle(&mut server, &red, &blue);
+
+    let clients = [&red, &blue];
+    let mut received = clients.iter().map(|_| Vec::new()).collect::<Vec<_>>();
+    let mut selected = false;
+    for _ in 0..MAX_UPDATES {
+        server.update(0.0);
+        for (client, received) in clients.iter().zip(received.iter_mut()) {
+            received.extend(client.received());
+        }
+        let all = |f: fn(&ServerMessage<Id>) -> bool| {
+            received.iter().all(|received| {
+                received
+                    .iter()
+                    .any(|message| matches!(message, NetServerMessage::Game(message) if f(message)))
+            })
+        };
+        if !selected && all(|message| matches!(message, ServerMessage::StartSelecting)) {
+            for client in clients.iter() {
+                client.send(NetClientMessage::Game(ClientMessage::Move(
+                    0,
+                    BattleMove::Move(0, None),
+                )));
+            }
+            selected = true;
+        } else if selected && all(|message| matches!(message, ServerMessage::TurnQueue(..))) {
+            break;
+        }
+    }
+    assert!(selected, "Battle did not ask for moves: {:?}", received);
+
+    received
+        .iter()
+        .map(|received| format!("{:?}", received))
+        .collect()
+}
+
+#[test]
+fn same_seed_reproduces_battle() {
+    // The parties are part of the battle's first message
+    let first = first_turn(7);
+    assert!(
+        first.iter().all(|received| received.contains("TurnQueue")),
+        "{:?}",
+        first
+    );
+    assert_eq!(first, first_turn(7));
+}
+
+#[test]
+fn spectators_do_not_see_parties() {
+    let (mut server, loopback) = start();
+    let red = connect(&loopback, 1);
+    let blue = connect(&loopback, 2);
+    let spectator = connect(&loopback, 3);
+
+    battle(&mut server, &red, &blue);
+    for _ in 0..MAX_UPDATES {
+        server.update(0.0);
+        red.received();
+        blue.received();
+    }
+
+    spectator.send(NetClientMessage::Spectate(None));
+    server.update(0.0);
+    let received = spectator.received();
+    match received.as_slice() {
+        [NetServerMessage::Validate(ConnectMessage::Spectating(..)), NetServerMessage::Setup(setup), NetServerMessage::Game(ServerMessage::Begin(data)), rest @ ..] =>
+        {
+            assert_eq!(setup.you, None);
+            // Neither player's party is shown, only pokemon as they are revealed
+            assert_eq!(format!("{:?}", data.local.pokemon), "[]");
+            assert!(
+                rest.iter().all(|message| matches!(
+                    message,
+                    NetServerMessage::Game(
+                        ServerMessage::TurnQueue(..)
+                            | ServerMessage::FaintReplace(..)
+                            | ServerMessage::AddUnknown(..)
+                            | ServerMessage::GameEnd(..)
+                    )
+                )),
+                "{:?}",
+                rest
+            );
+        }
+        other => panic!("Expected to spectate the battle, got {:?}", other),
+    }
+}
@@ -0,0 +1,58 @@
+//! Player accounts and the records kept for them.
+
+use core::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+/// Longest account name a server accepts.
+pub const MAX_NAME_LENGTH: usize = 16;
+
+/// Wins, losses and draws of an account, and its most recent battles.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct PlayerStats {
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+    /// Most recent battles, oldest first.
+    pub history: Vec<MatchRecord>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MatchRecord {
+    /// Seconds since the unix epoch when the battle finished.
+    pub time: u64,
+    pub result: MatchResult,
+    /// Human players battled against, empty if the opponents were all AI.
+    pub opponents: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum MatchResult {
+    Win,
+    Loss,
+    Draw,
+}
+
+/// Why the server did not let a player join with the name they gave.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum LoginError {
+    WrongPassword,
+    /// An account has this name, and no password was given
+    NameTaken,
+    InvalidName,
+    /// The server is running a tournament the name is not registered for
+    NotRegistered,
+}
+
+impl Display for LoginError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::WrongPassword => f.write_str("Wrong password"),
+            Self::NameTaken => f.write_str("Name belongs to an account, give its password"),
+            Self::InvalidName => {
+                write!(f, "Names must be 1 to {} characters long", MAX_NAME_LENGTH)
+            }
+            Self::NotRegistered => f.write_str("Name is not registered for the tournament"),
+        }
+    }
+}
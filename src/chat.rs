@@ -0,0 +1,47 @@
+//! Text chat between players in the same room or battle.
+
+use core::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+/// Longest chat message a server accepts, in characters.
+pub const MAX_CHAT_LENGTH: usize = 150;
+
+/// Who a chat message was sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ChatScope {
+    /// Everyone in the sender's lobby room
+    Room,
+    /// Players and spectators of the sender's battle
+    Battle,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChatMessage {
+    pub scope: ChatScope,
+    /// Name of the player that sent the message.
+    pub from: String,
+    pub text: String,
+}
+
+/// Why the server did not pass on a chat message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ChatError {
+    Empty,
+    TooLong,
+    /// Sender has sent too many messages recently
+    TooFast,
+    /// Sender is not in a room or battle to chat in
+    NoChannel,
+}
+
+impl Display for ChatError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Empty => f.write_str("Message is empty"),
+            Self::TooLong => write!(f, "Message is over {} characters", MAX_CHAT_LENGTH),
+            Self::TooFast => f.write_str("Sending messages too quickly"),
+            Self::NoChannel => f.write_str("Not in a room or battle"),
+        }
+    }
+}
@@ -0,0 +1,12 @@
+//! Content hashes of data bundles.
+//! This module is also compiled into the build script, so it cannot depend on the rest of the crate.
+
+const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Hash the bytes of a bundle with 64 bit FNV-1a, which is the same on every platform and build.
+pub fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(OFFSET, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(PRIME)
+    })
+}
@@ -4,49 +4,225 @@ pub use battle::pokedex;
 pub use bincode::{deserialize, serialize, Error as SerdeError};
 pub extern crate bincode;
 
+pub mod account;
+pub mod chat;
+pub mod hash;
+pub mod lobby;
+pub mod ranking;
+pub mod reliable;
+pub mod replay;
+#[cfg(feature = "socket")]
+pub mod socket;
+pub mod tournament;
+
+use account::{LoginError, PlayerStats};
 use battle::{
     message::{ClientMessage, ServerMessage},
     pokedex::pokemon::{owned::SavedPokemon, party::Party},
 };
+use chat::{ChatError, ChatMessage};
+use lobby::{BattleFormat, LobbyMessage, LobbyRequest};
+use ranking::{RankingMessage, RankingRequest};
 use serde::{Deserialize, Serialize};
+use tournament::Bracket;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Version of the messages sent between clients and servers.
+pub type ProtocolVersion = u16;
+
+/// Protocol version spoken by this crate, increased whenever a message is added or changed.
+pub const PROTOCOL_VERSION: ProtocolVersion = 10;
+
+/// Oldest protocol version a server built from this crate still accepts.
+/// Only raised when a message is changed or removed, as clients skip messages added after them.
+/// Version 10 removed tag battles and changed the battle setup.
+pub const MIN_PROTOCOL_VERSION: ProtocolVersion = 10;
+
+include!(concat!(env!("OUT_DIR"), "/bundles.rs"));
+
 pub type Id = u8;
 
+/// Identifies a battle running on a server.
+pub type BattleId = u64;
+
+/// Given to players when they join, used to take their place in a battle back after reconnecting.
+pub type SessionToken = u64;
+
 pub const DEFAULT_PORT: u16 = 28528;
 // pub const PROTOCOL: Transport = Transport::FramedTcp;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub enum NetClientMessage<ID> {
-    /// Request to connect with version string
-    RequestJoin(String),
-    /// Join the server
+    /// Request to connect with the client's protocol version and hashes of its data bundles.
+    /// Stays the first variant with the protocol version first, so any server can read the version.
+    RequestJoin(ProtocolVersion, BundleHash),
+    /// Submit a party to battle with
+    Team(Party<SavedPokemon>),
+    /// Join the server's lobby
     Join(Player),
+    /// Reconnect to a battle in progress with the token given when joining
+    Rejoin(SessionToken),
+    /// Watch a battle in progress, or any battle if none is given
+    Spectate(Option<BattleId>),
     /// Send game messages to server
     Game(ClientMessage<ID>),
     /// Leave game
     Leave,
+    /// Check that the server is still responding
+    Ping,
+    /// Find or create a room to battle in
+    Lobby(LobbyRequest),
+    /// Say something to the room or battle the client is in
+    Chat(String),
+    /// Vote on playing again with the same players after a battle ends
+    Rematch(RematchVote),
+    /// Ask for the record of the account with the given name
+    Stats(String),
+    /// Ask for the leaderboard or an account's rating history
+    Ranking(RankingRequest),
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub enum NetServerMessage<ID> {
     Validate(ConnectMessage),
     Game(ServerMessage<ID>),
+    /// Reply to [NetClientMessage::Ping]
+    Pong,
+    /// Time the player has left to act, sent when their clock starts and stops
+    Clock(Clock),
+    /// The player's clock ran out and the server acted for them
+    TimedOut,
+    Lobby(LobbyMessage),
+    Chat(ChatMessage),
+    /// A chat message the client sent was not passed on
+    ChatRejected(ChatError),
+    Rematch(RematchMessage),
+    /// Record of the account with the given name, if there is one
+    Stats(String, Option<PlayerStats>),
+    Ranking(RankingMessage),
+    /// State of the tournament the server is running
+    Tournament(Bracket),
+    /// Format and teams of a battle, sent to its players and spectators before it begins
+    Setup(BattleSetup),
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub enum ConnectMessage {
-    CanJoin(Party<SavedPokemon>),
+    /// Client's protocol version is not supported, with the versions the server does support.
+    /// Stays the first variant, so any client can read it.
+    WrongVersion(ProtocolRange),
+    /// Client can join, with a random party if the server generates them
+    CanJoin(Option<Party<SavedPokemon>>),
+    /// Server accepted the submitted party
+    TeamAccepted,
+    /// Server does not accept submitted parties
+    TeamRejected,
+    /// Submitted party breaks the server's rules, with the reason for each problem
+    IllegalTeam(Vec<String>),
+    /// Token to rejoin the battle with after losing connection
+    Session(SessionToken),
+    /// Client has taken its place back in a battle, followed by every game message sent to it so far
+    Rejoined,
+    /// No battle in progress has a player with the given token
+    UnknownSession,
+    /// Client is watching a battle, followed by every public game message sent so far
+    Spectating(BattleId),
+    /// There is no battle in progress to watch
+    NoBattle,
     /// Client has not requested to join by sending version
     NoRequest,
     AlreadyConnected,
     ConnectionReplaced,
-    WrongVersion,
+    /// Client was built with different dex or battle data than the server is running
+    WrongBundles,
     InProgress,
+    /// Client could not log in to the account with the name it gave
+    LoginFailed(LoginError),
+}
+
+/// Format of a battle and who is playing in it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BattleSetup {
+    pub format: BattleFormat,
+    /// Name of each player, in battle order.
+    pub players: Vec<Option<String>>,
+    /// Index of the receiving client's player, or `None` for spectators.
+    pub you: Option<u8>,
+}
+
+/// A player's answer to a rematch offer.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct RematchVote {
+    pub accept: bool,
+    /// Battle with the same party again, instead of a new random one if the server generates them.
+    pub same_team: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub enum RematchMessage {
+    /// The battle has ended and the players can vote for a rematch within the given seconds
+    Offer(f32),
+    /// Votes so far in seat order, with `None` for players that have not voted
+    Votes(Vec<Option<bool>>),
+    /// Someone declined, left or did not vote in time
+    Cancelled,
+    /// Everyone accepted, with the client's new party if it changed
+    Starting(Option<Party<SavedPokemon>>),
+}
+
+/// Seconds a player has left to act, which clients count down while the clock is running.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct Clock {
+    /// Time left for the current turn, if turns are timed and the clock is running.
+    pub turn: Option<f32>,
+    /// Time left for the rest of the battle, if the battle is timed.
+    pub total: Option<f32>,
+    pub running: bool,
+}
+
+/// Protocol versions a server accepts.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ProtocolRange {
+    pub min: ProtocolVersion,
+    pub max: ProtocolVersion,
+    /// Crate version of the server, which clients can upgrade or downgrade to.
+    pub version: String,
+}
+
+impl ProtocolRange {
+    /// Versions accepted by a server built from this crate.
+    pub fn supported() -> Self {
+        Self {
+            min: MIN_PROTOCOL_VERSION,
+            max: PROTOCOL_VERSION,
+            version: VERSION.to_owned(),
+        }
+    }
+
+    pub fn contains(&self, protocol: ProtocolVersion) -> bool {
+        (self.min..=self.max).contains(&protocol)
+    }
+}
+
+/// Hashes of the data bundles a client or server is running with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BundleHash {
+    pub dex: u64,
+    pub battle: u64,
+}
+
+impl BundleHash {
+    /// Hashes of the bundles this crate was built with.
+    pub const BUILT: Self = Self {
+        dex: DEX_HASH,
+        battle: BATTLE_HASH,
+    };
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Player {
     pub name: String,
-}
\ No newline at end of file
+    /// Password of the account with this name, which is created if it does not exist yet.
+    pub password: Option<String>,
+}
@@ -0,0 +1,211 @@
+//! Rooms players gather in before a battle starts.
+
+use core::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// Identifies a room in a server's lobby.
+pub type RoomId = u32;
+
+/// Longest room name a server accepts.
+pub const MAX_ROOM_NAME: usize = 24;
+
+/// Most players a free-for-all battle can have.
+pub const MAX_PLAYERS: u8 = 4;
+
+/// How many players battle and how many pokemon each has out at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BattleFormat {
+    Singles,
+    Doubles,
+    Triples,
+    /// The given number of players, each on their own
+    FreeForAll(u8),
+}
+
+impl BattleFormat {
+    /// The one on one format with the given number of active pokemon per player.
+    pub fn from_active(active: u8) -> Option<Self> {
+        match active {
+            1 => Some(Self::Singles),
+            2 => Some(Self::Doubles),
+            3 => Some(Self::Triples),
+            _ => None,
+        }
+    }
+
+    /// Number of active pokemon per player.
+    pub fn active(&self) -> usize {
+        match self {
+            Self::Singles | Self::FreeForAll(..) => 1,
+            Self::Doubles => 2,
+            Self::Triples => 3,
+        }
+    }
+
+    /// Number of players in a battle, including AI players.
+    pub fn players(&self) -> usize {
+        match self {
+            Self::Singles | Self::Doubles | Self::Triples => 2,
+            Self::FreeForAll(players) => *players as _,
+        }
+    }
+
+    /// Check that a battle can be played in the format.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::FreeForAll(players) if !(3..=MAX_PLAYERS).contains(players) => Err(format!(
+                "Free-for-all battles need 3 to {} players",
+                MAX_PLAYERS
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Display for BattleFormat {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Singles => f.write_str("singles"),
+            Self::Doubles => f.write_str("doubles"),
+            Self::Triples => f.write_str("triples"),
+            Self::FreeForAll(players) => write!(f, "ffa{}", players),
+        }
+    }
+}
+
+impl FromStr for BattleFormat {
+    type Err = String;
+
+    /// Parse a format name, with free-for-all written as `ffa` and an optional player count, like `ffa3`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        let format = match s.as_str() {
+            "singles" => Self::Singles,
+            "doubles" => Self::Doubles,
+            "triples" => Self::Triples,
+            "ffa" => Self::FreeForAll(MAX_PLAYERS),
+            "tag" => {
+                return Err(
+                    "Tag battles are not supported, every player battles on their own".to_owned(),
+                )
+            }
+            other => match other.strip_prefix("ffa").map(str::parse) {
+                Some(Ok(players)) => Self::FreeForAll(players),
+                _ => return Err(format!("Unknown battle format {}", other)),
+            },
+        };
+        format.validate().map(|()| format)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RoomSettings {
+    pub name: String,
+    pub format: BattleFormat,
+    /// Password needed to join the room, if it is private.
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub enum LobbyRequest {
+    /// List the open rooms
+    Rooms,
+    /// Create a room and join it as its owner
+    Create(RoomSettings),
+    /// Join a room, with its password if it has one
+    Join(RoomId, Option<String>),
+    /// Leave the current room
+    Leave,
+    /// Set whether the player is ready to start
+    Ready(bool),
+    /// Remove the player at an index of the room's members, if the player owns the room
+    Kick(u8),
+    /// Leave any room and battle whoever else is waiting
+    QuickMatch,
+    /// Leave any room and battle a player with a similar rating
+    RankedMatch,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub enum LobbyMessage {
+    Rooms(Vec<RoomInfo>),
+    /// The player's current room, sent whenever it changes
+    Room(RoomState),
+    /// The player has left their room
+    Left,
+    /// The player was removed from their room by its owner
+    Kicked,
+    /// The player is waiting for a quick or ranked match
+    Queued,
+    Error(LobbyError),
+}
+
+/// A room as shown in the room list.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RoomInfo {
+    pub id: RoomId,
+    pub name: String,
+    pub format: BattleFormat,
+    pub players: u8,
+    pub capacity: u8,
+    /// If a password is needed to join.
+    pub locked: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RoomState {
+    pub info: RoomInfo,
+    pub members: Vec<RoomMember>,
+    /// Index of the room's owner in its members.
+    pub owner: u8,
+    /// Index of the receiving player in the members.
+    pub you: u8,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RoomMember {
+    pub name: String,
+    pub ready: bool,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub enum LobbyError {
+    RoomNotFound,
+    WrongPassword,
+    RoomFull,
+    /// Only the room's owner can do this
+    NotOwner,
+    NotInRoom,
+    /// The player needs a name and party before entering a room
+    NotJoined,
+    /// The room's name is empty or too long
+    InvalidName,
+    /// Ranked battles need the player to be logged in to an account
+    NoAccount,
+    /// The server only runs the battles of its tournament
+    Tournament,
+    /// The format has an unsupported number of players
+    InvalidFormat,
+    /// Ranked battles are only played between two players
+    NotRanked,
+}
+
+impl Display for LobbyError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Self::RoomNotFound => "Room does not exist",
+            Self::WrongPassword => "Wrong password",
+            Self::RoomFull => "Room is full",
+            Self::NotOwner => "Only the room's owner can do that",
+            Self::NotInRoom => "Not in a room",
+            Self::NotJoined => "Join the server before entering a room",
+            Self::InvalidName => "Room name is empty or too long",
+            Self::NoAccount => "Log in to an account to battle ranked",
+            Self::Tournament => "The server is running a tournament",
+            Self::InvalidFormat => "Battle format has an unsupported number of players",
+            Self::NotRanked => "The server's battles are not one on one, so they are not ranked",
+        })
+    }
+}
@@ -0,0 +1,63 @@
+//! Elo ratings of accounts from ranked battles.
+
+use serde::{Deserialize, Serialize};
+
+/// Rating accounts start with.
+pub const INITIAL_RATING: f64 = 1500.0;
+
+/// An account's rating and how it changed over its recent ranked battles.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Rating {
+    pub rating: f64,
+    /// Most recent changes, oldest first.
+    pub history: Vec<RatingChange>,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Self {
+            rating: INITIAL_RATING,
+            history: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct RatingChange {
+    /// Seconds since the unix epoch when the battle finished.
+    pub time: u64,
+    /// Rating after the battle.
+    pub rating: f64,
+    pub change: f64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LeaderboardEntry {
+    pub name: String,
+    pub rating: f64,
+    /// Number of ranked battles played.
+    pub battles: u32,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum RankingRequest {
+    /// Highest rated accounts
+    Leaderboard,
+    /// Rating history of the account with the given name
+    History(String),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum RankingMessage {
+    /// Highest rated accounts, best first
+    Leaderboard(Vec<LeaderboardEntry>),
+    /// Rating of the account with the given name, if there is one
+    History(String, Option<Rating>),
+    /// The client's rating changed after a ranked battle
+    Updated(RatingChange),
+}
+
+/// Chance of a player rated `rating` beating one rated `opponent`.
+pub fn expected(rating: f64, opponent: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf((opponent - rating) / 400.0))
+}
@@ -0,0 +1,297 @@
+//! Reliable, ordered delivery on top of an unreliable packet socket.
+//!
+//! Every payload is wrapped in a [Frame] with a sequence number.
+//! Receivers acknowledge data frames and hold back frames that arrive early, up to a window,
+//! senders retransmit frames that have not been acknowledged.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{deserialize, serialize, SerdeError};
+
+pub type Sequence = u32;
+
+/// Identifies one side of a connection, so that a restarted peer is not confused with the old one.
+pub type Session = u32;
+
+/// Seconds to wait for an acknowledgement before sending a frame again.
+pub const RESEND_INTERVAL: f32 = 0.25;
+
+/// Times a frame is sent again before the remote peer is given up on.
+pub const MAX_RESENDS: u32 = 40;
+
+/// How far ahead of the next expected frame frames are held back.
+/// Frames further ahead are dropped without an acknowledgement, so they are sent again later.
+pub const RECEIVE_WINDOW: Sequence = 256;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub enum Frame {
+    Data {
+        session: Session,
+        sequence: Sequence,
+        payload: Vec<u8>,
+    },
+    Ack {
+        session: Session,
+        sequence: Sequence,
+    },
+}
+
+/// The state of one connection between two peers.
+#[derive(Debug)]
+pub struct ReliableChannel {
+    session: Session,
+    next: Sequence,
+    pending: BTreeMap<Sequence, Pending>,
+    /// A frame went unacknowledged for too long, so the channel should be dropped.
+    failed: bool,
+
+    remote: Option<Session>,
+    expected: Sequence,
+    early: BTreeMap<Sequence, Vec<u8>>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    frame: Vec<u8>,
+    waited: f32,
+    resends: u32,
+}
+
+/// The result of receiving a frame.
+#[derive(Debug, Default)]
+pub struct Received {
+    /// Acknowledgement to send back to the remote peer.
+    pub ack: Option<Vec<u8>>,
+    /// Payloads that can now be delivered, in order.
+    pub payloads: Vec<Vec<u8>>,
+}
+
+impl ReliableChannel {
+    pub fn new(session: Session) -> Self {
+        Self {
+            session,
+            next: 0,
+            pending: Default::default(),
+            failed: false,
+            remote: None,
+            expected: 0,
+            early: Default::default(),
+        }
+    }
+
+    /// Wrap a payload in a frame to be sent, keeping it until it is acknowledged.
+    pub fn send(&mut self, payload: Vec<u8>) -> Result<Vec<u8>, SerdeError> {
+        let sequence = self.next;
+        let frame = serialize(&Frame::Data {
+            session: self.session,
+            sequence,
+            payload,
+        })?;
+        self.next = self.next.wrapping_add(1);
+        self.pending.insert(
+            sequence,
+            Pending {
+                frame: frame.clone(),
+                waited: 0.0,
+                resends: 0,
+            },
+        );
+        Ok(frame)
+    }
+
+    /// Process a frame from the remote peer.
+    pub fn receive(&mut self, frame: &[u8]) -> Result<Received, SerdeError> {
+        let mut received = Received::default();
+        match deserialize::<Frame>(frame)? {
+            Frame::Data {
+                session,
+                sequence,
+                payload,
+            } => {
+                if self.remote != Some(session) {
+                    // The remote peer has restarted and expects to hear from the start again
+                    if self.remote.is_some() {
+                        self.next = 0;
+                        self.pending.clear();
+                        self.failed = false;
+                    }
+                    self.remote = Some(session);
+                    self.expected = 0;
+                    self.early.clear();
+                }
+
+                let ahead = sequence.wrapping_sub(self.expected);
+                if ahead == 0 {
+                    received.payloads.push(payload);
+                    self.expected = self.expected.wrapping_add(1);
+                    while let Some(payload) = self.early.remove(&self.expected) {
+                        received.payloads.push(payload);
+                        self.expected = self.expected.wrapping_add(1);
+                    }
+                } else if ahead < RECEIVE_WINDOW {
+                    self.early.insert(sequence, payload);
+                } else if ahead < Sequence::MAX / 2 {
+                    // Too far ahead to hold on to
+                    return Ok(received);
+                }
+                // Frames from behind were delivered already, so they are acknowledged again too
+                received.ack = Some(serialize(&Frame::Ack { session, sequence })?);
+            }
+            Frame::Ack { session, sequence } => {
+                if session == self.session {
+                    self.pending.remove(&sequence);
+                }
+            }
+        }
+        Ok(received)
+    }
+
+    /// Advance time by `delta` seconds, returning frames that should be sent again.
+    /// Once a frame has been sent [MAX_RESENDS] times without an acknowledgement, nothing is sent again.
+    pub fn update(&mut self, delta: f32) -> Vec<Vec<u8>> {
+        if self
+            .pending
+            .values()
+            .any(|pending| pending.resends >= MAX_RESENDS)
+        {
+            self.failed = true;
+            self.pending.clear();
+        }
+        self.pending
+            .values_mut()
+            .filter_map(|pending| {
+                pending.waited += delta;
+                match pending.waited >= RESEND_INTERVAL {
+                    true => {
+                        pending.waited = 0.0;
+                        pending.resends += 1;
+                        Some(pending.frame.clone())
+                    }
+                    false => None,
+                }
+            })
+            .collect()
+    }
+
+    /// Whether the remote peer stopped acknowledging frames, so the channel should be dropped.
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+
+    /// Number of frames that have not been acknowledged yet.
+    pub fn unacknowledged(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(session: Session, sequence: Sequence, payload: u8) -> Vec<u8> {
+        serialize(&Frame::Data {
+            session,
+            sequence,
+            payload: vec![payload],
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn delivers_in_order() {
+        let mut sender = ReliableChannel::new(1);
+        let mut receiver = ReliableChannel::new(2);
+        for payload in 0..3 {
+            let frame = sender.send(vec![payload]).unwrap();
+            let received = receiver.receive(&frame).unwrap();
+            assert_eq!(received.payloads, vec![vec![payload]]);
+            sender.receive(&received.ack.unwrap()).unwrap();
+        }
+        assert_eq!(sender.unacknowledged(), 0);
+    }
+
+    #[test]
+    fn holds_back_early_frames() {
+        let mut sender = ReliableChannel::new(1);
+        let mut receiver = ReliableChannel::new(2);
+        let frames = (0..3)
+            .map(|payload| sender.send(vec![payload]).unwrap())
+            .collect::<Vec<_>>();
+
+        let received = receiver.receive(&frames[2]).unwrap();
+        assert!(received.payloads.is_empty());
+        assert!(received.ack.is_some());
+        let received = receiver.receive(&frames[0]).unwrap();
+        assert_eq!(received.payloads, vec![vec![0]]);
+        let received = receiver.receive(&frames[1]).unwrap();
+        assert_eq!(received.payloads, vec![vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn acknowledges_duplicates_again() {
+        let mut sender = ReliableChannel::new(1);
+        let mut receiver = ReliableChannel::new(2);
+        let frame = sender.send(vec![0]).unwrap();
+        sender.send(vec![1]).unwrap();
+
+        let first = receiver.receive(&frame).unwrap();
+        assert_eq!(first.payloads, vec![vec![0]]);
+        // The acknowledgement was lost, so the frame arrives again
+        let second = receiver.receive(&frame).unwrap();
+        assert!(second.payloads.is_empty());
+        assert_eq!(first.ack, second.ack);
+
+        let ack = second.ack.unwrap();
+        sender.receive(&ack).unwrap();
+        sender.receive(&ack).unwrap();
+        assert_eq!(sender.unacknowledged(), 1);
+    }
+
+    #[test]
+    fn drops_frames_outside_window() {
+        let mut receiver = ReliableChannel::new(2);
+        let received = receiver.receive(&data(1, RECEIVE_WINDOW, 0)).unwrap();
+        assert!(received.payloads.is_empty());
+        assert!(received.ack.is_none());
+        assert!(receiver.early.is_empty());
+
+        let received = receiver.receive(&data(1, RECEIVE_WINDOW - 1, 0)).unwrap();
+        assert!(received.ack.is_some());
+        assert_eq!(receiver.early.len(), 1);
+    }
+
+    #[test]
+    fn restarts_with_remote_session() {
+        let mut channel = ReliableChannel::new(1);
+        channel.receive(&data(2, 0, 0)).unwrap();
+        channel.receive(&data(2, 2, 2)).unwrap();
+        channel.send(vec![0]).unwrap();
+        channel.send(vec![1]).unwrap();
+
+        // The remote peer restarts with a new session and starts over
+        let received = channel.receive(&data(3, 0, 5)).unwrap();
+        assert_eq!(received.payloads, vec![vec![5]]);
+        assert!(channel.early.is_empty());
+        assert_eq!(channel.unacknowledged(), 0);
+        let frame = channel.send(vec![6]).unwrap();
+        assert!(matches!(
+            deserialize::<Frame>(&frame).unwrap(),
+            Frame::Data { sequence: 0, .. }
+        ));
+    }
+
+    #[test]
+    fn gives_up_after_max_resends() {
+        let mut channel = ReliableChannel::new(1);
+        channel.send(vec![0]).unwrap();
+        for _ in 0..MAX_RESENDS {
+            assert_eq!(channel.update(RESEND_INTERVAL).len(), 1);
+        }
+        assert!(!channel.failed());
+        assert!(channel.update(RESEND_INTERVAL).is_empty());
+        assert!(channel.failed());
+        assert_eq!(channel.unacknowledged(), 0);
+    }
+}
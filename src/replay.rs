@@ -0,0 +1,98 @@
+//! Recorded battles that can be played back.
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    battle::message::ServerMessage,
+    deserialize,
+    pokedex::pokemon::{owned::SavedPokemon, party::Party},
+    serialize, SerdeError,
+};
+
+/// Version of the replay format, written at the start of every replay.
+pub const REPLAY_VERSION: u16 = 2;
+
+pub const REPLAY_EXTENSION: &str = "replay";
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Replay<ID> {
+    pub version: u16,
+    /// Seed of the battle's random number generator, if it was seeded.
+    pub seed: Option<u64>,
+    /// Hash of the dex bundle the battle was run with, which is needed to play it back.
+    pub dex: u64,
+    pub players: Vec<ReplayPlayer<ID>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ReplayPlayer<ID> {
+    pub name: Option<String>,
+    pub party: Party<SavedPokemon>,
+    /// Every message sent to the player, empty for AI players.
+    pub messages: Vec<ReplayMessage<ID>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ReplayMessage<ID> {
+    /// Seconds since the battle started.
+    pub time: f32,
+    pub message: ServerMessage<ID>,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The replay was written with a different version of the format.
+    Version(u16),
+    /// The replay was recorded with a different dex bundle than the given one.
+    Dex(u64),
+    Serde(SerdeError),
+}
+
+impl<ID: Serialize + DeserializeOwned> Replay<ID> {
+    pub fn new(seed: Option<u64>, dex: u64) -> Self {
+        Self {
+            version: REPLAY_VERSION,
+            seed,
+            dex,
+            players: Default::default(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SerdeError> {
+        serialize(self)
+    }
+
+    /// Read a replay, checking that it can be played back with the dex bundle hashed to `dex`.
+    pub fn from_bytes(bytes: &[u8], dex: u64) -> Result<Self, ReplayError> {
+        // The version is the first field, so it can be read before the rest of the replay
+        let version = deserialize::<u16>(bytes).map_err(ReplayError::Serde)?;
+        if version != REPLAY_VERSION {
+            return Err(ReplayError::Version(version));
+        }
+        let replay = deserialize::<Self>(bytes).map_err(ReplayError::Serde)?;
+        match replay.dex == dex {
+            true => Ok(replay),
+            false => Err(ReplayError::Dex(replay.dex)),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl core::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Version(version) => write!(
+                f,
+                "Replay has version {} but version {} is supported",
+                version, REPLAY_VERSION
+            ),
+            Self::Dex(hash) => write!(
+                f,
+                "Replay was recorded with dex bundle {:016x}, which is not the one loaded",
+                hash
+            ),
+            Self::Serde(err) => core::fmt::Display::fmt(err, f),
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::{collections::VecDeque, net::SocketAddr};
+
+use crate::reliable::{ReliableChannel, Session};
+use log::{error, warn};
+use naia_client_socket::{
+    Packet, PacketReceiver as NaiaPacketReceiver, PacketSender as NaiaPacketSender, Socket,
+};
+
+pub type Endpoint = SocketAddr;
+
+/// A connection to the server with reliable, ordered delivery.
+pub struct ReliableSocket {
+    // Keep the socket alive for as long as the connection is
+    _socket: Socket,
+    sender: NaiaPacketSender,
+    receiver: NaiaPacketReceiver,
+    channel: ReliableChannel,
+    received: VecDeque<Vec<u8>>,
+}
+
+impl ReliableSocket {
+    pub fn connect(address: Endpoint, session: Session) -> Self {
+        let mut socket = Socket::new(Default::default());
+
+        socket.connect(address);
+
+        let sender = socket.get_packet_sender();
+        let receiver = socket.get_packet_receiver();
+
+        Self {
+            _socket: socket,
+            sender,
+            receiver,
+            channel: ReliableChannel::new(session),
+            received: Default::default(),
+        }
+    }
+
+    pub fn send(&mut self, bytes: Vec<u8>) {
+        match self.channel.send(bytes) {
+            Ok(frame) => self.sender.send(Packet::new(frame)),
+            Err(err) => error!("Could not frame packet with error {}", err),
+        }
+    }
+
+    /// Receive the next payload from the server, in the order it was sent.
+    pub fn receive(&mut self) -> Option<Vec<u8>> {
+        while self.received.is_empty() {
+            let packet = match self.receiver.receive() {
+                Ok(packet) => packet?,
+                Err(..) => return None,
+            };
+            match self.channel.receive(packet.payload()) {
+                Ok(received) => {
+                    if let Some(ack) = received.ack {
+                        self.sender.send(Packet::new(ack));
+                    }
+                    self.received.extend(received.payloads);
+                }
+                Err(err) => warn!("Could not read frame from server with error {}", err),
+            }
+        }
+        self.received.pop_front()
+    }
+
+    /// Send frames that have not been acknowledged in time again.
+    pub fn update(&mut self, delta: f32) {
+        for frame in self.channel.update(delta) {
+            self.sender.send(Packet::new(frame));
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! Brackets of tournaments run by a server.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TournamentFormat {
+    /// Players are out after losing a battle
+    SingleElimination,
+    /// Players battle others with the same score for a set number of rounds
+    Swiss,
+}
+
+/// The state of a tournament, sent to clients whenever it changes.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Bracket {
+    pub format: TournamentFormat,
+    /// Rounds played so far, including the current one.
+    pub rounds: Vec<Vec<BracketMatch>>,
+    /// Number of rounds the tournament will have.
+    pub total_rounds: u32,
+    /// Players in order of their standing, with their points in Swiss tournaments.
+    pub standings: Vec<Standing>,
+    /// Winner of the tournament once it has finished.
+    pub winner: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BracketMatch {
+    /// Players in the match, a single player has a bye.
+    pub players: Vec<String>,
+    pub outcome: Option<MatchOutcome>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum MatchOutcome {
+    Won(String),
+    Drew,
+    /// Player won as their opponent did not show up, or no one did
+    NoShow(Option<String>),
+    Bye,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Standing {
+    pub name: String,
+    pub points: f32,
+    pub eliminated: bool,
+}